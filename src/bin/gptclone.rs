@@ -1,18 +1,125 @@
 extern crate libpart;
 
 use std::env;
-use std::fs::{File, OpenOptions};
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::process;
 use libpart::gpt;
 
+fn fail<E: Display>(msg: &str, err: E) -> ! {
+    eprintln!("{}: {}", msg, err);
+    process::exit(1);
+}
+
 fn main() {
     let args = env::args().collect::<Vec<_>>();
-    let input = &args[1];
-    let output = &args[2];
+    let keep_backup = args.iter().any(|a| a == "--keep-backup-position");
+    let new_guids = args.iter().any(|a| a == "--new-guids");
+    let force = args.iter().any(|a| a == "--force");
+    let files = args.iter().skip(1).filter(|a| !a.starts_with("--")).collect::<Vec<_>>();
+    if files.len() != 2 {
+        eprintln!("Usage: {} [--keep-backup-position] [--new-guids] [--force] <input> <output>", args[0]);
+        process::exit(1);
+    }
+    let input = files[0];
+    let output = files[1];
+    let options = gpt::GPTOptions::default();
+
+    let mut ifile = File::open(input).unwrap_or_else(|e| fail(&format!("Could not open {}", input), e));
+    let mut ofile = OpenOptions::new().read(true).write(true).create(false).open(output)
+        .unwrap_or_else(|e| fail(&format!("Could not open {}", output), e));
+
+    let mut gpt = gpt::GPTTable::load(&mut ifile, &options)
+        .unwrap_or_else(|e| fail(&format!("Could not read GPT from {}", input), e));
+
+    if !keep_backup {
+        let len = ofile.seek(SeekFrom::End(0))
+            .unwrap_or_else(|e| fail(&format!("Could not get the size of {}", output), e));
+        gpt.relocate_backup(len / options.block_size as u64, &options)
+            .unwrap_or_else(|e| fail(&format!("GPT does not fit on {}", output), e));
+    }
+
+    if new_guids {
+        gpt.regenerate_disk_guid();
+        gpt.regenerate_partition_guids();
+    }
+
+    if !force {
+        // Save everything we are about to overwrite, so it can be restored with dd
+        let snapshot = gpt.snapshot_raw(&mut ofile, &options)
+            .unwrap_or_else(|e| fail(&format!("Could not back up the GPT of {}, use --force to skip it", output), e));
+        println!("Saved the regions of {} about to be overwritten, restore them with:", output);
+        for (offset, bytes) in snapshot {
+            let backup = format!("{}.gptbak.{}", output, offset);
+            fs::write(&backup, bytes)
+                .unwrap_or_else(|e| fail(&format!("Could not write backup to {}, use --force to skip it", backup), e));
+            println!("  dd if={} of={} bs=512 seek={} oflag=seek_bytes conv=notrunc", backup, output, offset);
+        }
+    }
+
+    gpt.write(&mut ofile, &options)
+        .unwrap_or_else(|e| fail(&format!("Could not write GPT to {}", output), e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libpart::util::Block;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn clone_to_larger_disk() {
+        let options = gpt::GPTOptions::default();
+        let mut source = gpt::GPTTable::create(200, &options).unwrap();
+        let part = gpt::PartitionEntryBuilder::new()
+            .part_type(*gpt::types::LINUX_FILESYSTEM)
+            .range(Block(40), Block(99))
+            .name("data")
+            .build()
+            .unwrap();
+        source.set_partition(0, part).unwrap();
+        let mut input = Cursor::new(vec![0u8; 200 * 512]);
+        source.write(&mut input, &options).unwrap();
+
+        // Same steps as main, with the output twice the size of the input
+        let mut output = Cursor::new(vec![0xAAu8; 400 * 512]);
+        let mut gpt = gpt::GPTTable::load(&mut input, &options).unwrap();
+        let len = output.seek(SeekFrom::End(0)).unwrap();
+        gpt.relocate_backup(len / options.block_size as u64, &options).unwrap();
+        gpt.regenerate_disk_guid();
+        gpt.regenerate_partition_guids();
+        let snapshot = gpt.snapshot_raw(&mut output, &options).unwrap();
+        gpt.write(&mut output, &options).unwrap();
 
-    let mut ifile = File::open(input).unwrap();
-    let mut ofile = OpenOptions::new().read(true).write(true).create(false).open(output).unwrap();
+        let cloned = gpt::GPTTable::load(&mut output, &options).unwrap();
+        assert_eq!(cloned.backup_header_lba(), Block(399));
+        assert!(cloned.disk_guid() != source.disk_guid());
+        let (_, part) = cloned.used_partitions().next().unwrap();
+        assert_eq!((part.start, part.end), (Block(40), Block(99)));
+        assert_eq!(part.name, "data");
+        assert!(part.part_id != source.partitions()[0].as_ref().unwrap().part_id);
 
-    let gpt = gpt::GPTTable::load(&mut ifile, &gpt::GPTOptions::default()).unwrap();
+        // The snapshot holds the old contents of every region written
+        for (offset, bytes) in snapshot {
+            assert!(bytes.iter().all(|&b| b == 0xAA));
+            let mut written = vec![0u8; bytes.len()];
+            output.seek(SeekFrom::Start(offset)).unwrap();
+            output.read_exact(&mut written).unwrap();
+            assert!(written != bytes);
+        }
+    }
 
-    gpt.write(&mut ofile, &gpt::GPTOptions::default()).unwrap();
+    #[test]
+    fn clone_to_smaller_disk_fails() {
+        let options = gpt::GPTOptions::default();
+        let mut gpt = gpt::GPTTable::create(400, &options).unwrap();
+        let part = gpt::PartitionEntryBuilder::new()
+            .part_type(*gpt::types::LINUX_FILESYSTEM)
+            .range(Block(300), Block(350))
+            .build()
+            .unwrap();
+        gpt.set_partition(0, part).unwrap();
+        assert!(gpt.relocate_backup(200, &options).is_err());
+    }
 }
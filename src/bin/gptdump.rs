@@ -1,30 +1,128 @@
 extern crate libpart;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_json;
 
 use libpart::gpt;
 use std::env;
+use std::fmt::Display;
 use std::fs::File;
+use std::process;
+
+fn fail<E: Display>(msg: &str, err: E) -> ! {
+    eprintln!("{}: {}", msg, err);
+    process::exit(1);
+}
+
+#[cfg(feature = "serde")]
+fn dump_json(table: &gpt::GPTTable, options: &gpt::GPTOptions) -> serde_json::Value {
+    let (first_usable, last_usable) = table.usable_range();
+    let partitions = table.used_partitions().map(|(id, info)| {
+        json!({
+            "id": id,
+            "type_uuid": info.part_type.to_string(),
+            "type_name": gpt::types::name_for_type(&info.part_type),
+            "part_uuid": info.part_id.to_string(),
+            "start": info.start.0,
+            "end": info.end.0,
+            "size_bytes": info.size_bytes(options.block_size),
+            "flags": info.flags,
+            "name": info.name
+        })
+    }).collect::<Vec<_>>();
+
+    json!({
+        "disk_guid": table.disk_guid().to_string(),
+        "first_usable": first_usable.0,
+        "last_usable": last_usable.0,
+        "partitions": partitions
+    })
+}
+
+#[cfg(feature = "serde")]
+fn print_json(table: &gpt::GPTTable, options: &gpt::GPTOptions) {
+    println!("{}", dump_json(table, options));
+}
+
+#[cfg(not(feature = "serde"))]
+fn print_json(_table: &gpt::GPTTable, _options: &gpt::GPTOptions) {
+    fail("Can not dump JSON", "gptdump was built without the serde feature");
+}
 
 fn main() {
 
     let args = env::args().collect::<Vec<_>>();
+    let json = args.iter().any(|a| a == "--json");
+    let args = args.into_iter().filter(|a| a != "--json").collect::<Vec<_>>();
+
+    if args.len() != 2 {
+        eprintln!("Usage: {} [--json] <file>", args[0]);
+        process::exit(1);
+    }
 
-    println!("{:?}", args);
+    if !json {
+        println!("{:?}", args);
+    }
 
     let file_name = &args[1];
 
-    println!("Reading GPT of {}", file_name);
+    if !json {
+        println!("Reading GPT of {}", file_name);
+    }
 
-    let mut file = File::open(file_name).unwrap();
+    let mut file = File::open(file_name).unwrap_or_else(|e| fail(&format!("Could not open {}", file_name), e));
 
-    let table = gpt::GPTTable::load(&mut file, &gpt::GPTOptions::default()).unwrap();
+    let options = gpt::GPTOptions::default();
+    let table = gpt::GPTTable::load(&mut file, &options)
+        .unwrap_or_else(|e| fail(&format!("Could not read GPT from {}", file_name), e));
+
+    if json {
+        print_json(&table, &options);
+        return;
+    }
 
     println!("You have {} partition(s)", table.part_count());
 
-    for p in table.partitions().iter().enumerate().filter(|p| p.1.is_some()) {
-        match p {
-            (id, &Some(ref info)) => println!("Partition #{}: Type {} is called \"{}\"", id + 1, info.part_type, info.name),
-            _ => unreachable!("FUCKEM")
-        }
+    for (id, info) in table.used_partitions() {
+        let type_name = match gpt::types::name_for_type(&info.part_type) {
+            Some(name) => String::from(name),
+            None => info.part_type.to_string()
+        };
+        println!("Partition #{}: Type {} is called \"{}\"", id + 1, type_name, info.name)
     }
 
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use libpart::util::Block;
+
+    #[test]
+    fn json_dump() {
+        let options = gpt::GPTOptions::default();
+        let mut table = gpt::GPTTable::create(300, &options).unwrap();
+        let part = gpt::PartitionEntryBuilder::new()
+            .part_type(*gpt::types::LINUX_SWAP)
+            .range(Block(40), Block(49))
+            .name("tab\there \"quoted\"")
+            .build()
+            .unwrap();
+        table.set_partition(2, part).unwrap();
+
+        // Parse the printed form, so escaping is covered as well
+        let json: serde_json::Value = serde_json::from_str(&dump_json(&table, &options).to_string()).unwrap();
+        let (first_usable, _) = table.usable_range();
+        assert_eq!(json["disk_guid"], table.disk_guid().to_string());
+        assert_eq!(json["first_usable"], first_usable.0);
+        let parts = json["partitions"].as_array().unwrap();
+        assert_eq!(parts.len(), 1);
+        // Ids match the indices used by GPTTable
+        assert_eq!(parts[0]["id"], 2);
+        assert_eq!(parts[0]["type_name"], "Linux swap");
+        assert_eq!(parts[0]["start"], 40);
+        assert_eq!(parts[0]["end"], 49);
+        assert_eq!(parts[0]["size_bytes"], 10 * 512);
+        assert_eq!(parts[0]["name"], "tab\there \"quoted\"");
+    }
+}
@@ -12,16 +12,25 @@ fn main() {
 
     let mut file = File::open(name).unwrap();
 
-    let mbr = mbr::MBR::load(&mut file).unwrap();
+    let mbr = mbr::MBR::load(&mut file, false).unwrap();
    
+    if mbr.is_protective() || mbr.is_hybrid() {
+        println!("This disk uses GPT, use gptdump to see all partitions");
+    }
+
     println!("You have {} MBR partition(s)", mbr.partition_count());
 
-    for p in mbr.partitions().iter().enumerate().filter(|p| p.1.is_some()) {
-        match p {
-            (num, &Some(ref p)) => {
-                println!("Partition #{}: Type: {}", num + 1, p.system_id);
-            },
-            _ => {}
+    for (num, p) in mbr.used_partitions() {
+        match mbr::type_name(p.system_id) {
+            Some(name) => println!("Partition #{}: Type: {} ({:#04x})", num + 1, name, p.system_id),
+            None => println!("Partition #{}: Type: {:#04x}", num + 1, p.system_id)
+        }
+    }
+
+    for (num, p) in mbr.logical_partitions().iter().enumerate() {
+        match mbr::type_name(p.system_id) {
+            Some(name) => println!("Partition #{}: Type: {} ({:#04x})", num + 5, name, p.system_id),
+            None => println!("Partition #{}: Type: {:#04x}", num + 5, p.system_id)
         }
     }
     
@@ -0,0 +1,86 @@
+use super::gpt::{GPTTable, GPTOptions, GPTError};
+use super::mbr::MBR;
+use std::io::{Read, Seek, SeekFrom, ErrorKind};
+
+/// The partition table found on a disk
+#[derive(Debug)]
+pub enum DiskLayout {
+    /// The disk is partitioned using GPT
+    Gpt(GPTTable),
+    /// The disk is partitioned using MBR. Boxed, as a MBR with its boot code is several times
+    /// the size of a GPTTable, which would make every `DiskLayout` that large.
+    Mbr(Box<MBR>),
+    /// Neither a GPT nor a MBR boot signature was found
+    Unknown
+}
+
+/// Find out which kind of partition table a disk uses and load it
+///
+/// A GPT header at block 1 takes precedence over the MBR, so a protective MBR is never returned.
+/// Images too small to hold either table are reported as `Unknown`.
+pub fn detect<T: Read + Seek>(read: &mut T, options: &GPTOptions) -> Result<DiskLayout, GPTError> {
+    if GPTTable::exists(read, options)? {
+        return Ok(DiskLayout::Gpt(GPTTable::load(read, options)?));
+    }
+
+    let mut sig = [0u8; 2];
+    read.seek(SeekFrom::Start(510))?;
+    match read.read_exact(&mut sig) {
+        // Too small to hold a MBR
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(DiskLayout::Unknown),
+        r => r?
+    }
+    if sig != [0x55, 0xAA] {
+        return Ok(DiskLayout::Unknown);
+    }
+
+    Ok(DiskLayout::Mbr(Box::new(MBR::load(read, false)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpt::{GPTTable, GPTOptions};
+    use mbr::{PartitionEntryBuilder, MbrWriteMode};
+    use util::{blank_image, gpt_image};
+    use std::io::Cursor;
+
+    #[test]
+    fn detects_gpt() {
+        let options = GPTOptions::default();
+        let table = GPTTable::create(300, &options).unwrap();
+        let mut image = gpt_image(&table, 300, &options);
+        match detect(&mut image, &options).unwrap() {
+            DiskLayout::Gpt(loaded) => assert!(loaded == table),
+            _ => panic!("GPT not detected")
+        }
+    }
+
+    #[test]
+    fn detects_mbr() {
+        let mut mbr = MBR::new();
+        let part = PartitionEntryBuilder::new().system_id(0x83).start_lba(1).sectors(10).build().unwrap();
+        mbr.add_partition(part).unwrap();
+        // Also a dump of only the first block
+        for &blocks in &[20u64, 1] {
+            let mut image = blank_image(blocks, 512);
+            mbr.write_mbr(&mut image, MbrWriteMode::LbaOnly).unwrap();
+
+            match detect(&mut image, &GPTOptions::default()).unwrap() {
+                DiskLayout::Mbr(loaded) => assert_eq!(loaded.partition_count(), 1),
+                _ => panic!("MBR not detected")
+            }
+        }
+    }
+
+    #[test]
+    fn small_images_are_unknown() {
+        let options = GPTOptions::default();
+        for &len in &[0usize, 100, 512, 1000] {
+            let mut image = Cursor::new(vec![0u8; len]);
+            let layout = detect(&mut image, &options).unwrap();
+            assert!(matches!(layout, DiskLayout::Unknown), "{:?}", layout);
+        }
+        assert!(matches!(detect(&mut blank_image(20, 512), &options).unwrap(), DiskLayout::Unknown));
+    }
+}
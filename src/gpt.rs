@@ -3,15 +3,47 @@ extern crate uuid;
 extern crate byteorder;
 
 use super::util::Block;
+use super::mbr::{MBR, MBRError, MbrWriteMode, PartitionEntry as MBRPartitionEntry};
+use super::window::Window;
 use std::cmp;
 use self::checksum::crc32::Crc32 as CRC32;
 use self::uuid::{Uuid as UUID, ParseError as UUIDError};
 use self::byteorder::{WriteBytesExt, ReadBytesExt, LittleEndian, ByteOrder};
-use std::io::{Result as IOResult, Write, Read, Error as IOError, Seek, SeekFrom, Cursor};
+use std::io::{Result as IOResult, Write, Read, Error as IOError, ErrorKind, Seek, SeekFrom, Cursor, sink};
 use std::error::Error;
 use std::fmt;
 
+pub mod types;
+mod raw;
+
+use self::raw::RawHeader;
+
 const GPT_MAGIC: [u8; 8] = [0x45, 0x46, 0x49, 0x20, 0x50, 0x41, 0x52, 0x54];
+const DEFAULT_PART_COUNT: u64 = 128;
+/// Upper limit for the number of partition slots, so a corrupt header can not make us allocate
+/// huge amounts of memory. Far more than any tool creates.
+const MAX_PART_COUNT: u32 = 1 << 16;
+/// Number of partition entries serialized at once when writing the partition table
+const ENTRIES_PER_CHUNK: usize = 32;
+
+/// Attribute flag: the partition is required for the platform to function
+pub const FLAG_REQUIRED: u64 = 1 << 0;
+/// Attribute flag: the firmware should not provide block IO for the partition
+pub const FLAG_NO_BLOCK_IO: u64 = 1 << 1;
+/// Attribute flag: the partition is bootable for legacy BIOS
+pub const FLAG_LEGACY_BOOTABLE: u64 = 1 << 2;
+const TYPE_SPECIFIC_SHIFT: u64 = 48;
+
+/// How to handle partition names which are invalid UTF-16
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Utf16Mode {
+    /// Fail loading the GPT
+    Strict,
+    /// Replace invalid code units with the replacement character
+    Lossy,
+    /// Use an empty name for the partition
+    EmptyOnError
+}
 
 /// Options for parsing GPT
 pub struct GPTOptions {
@@ -19,8 +51,16 @@ pub struct GPTOptions {
     pub block_size: u16,
     /// Make checksum errors non-fatal.
     pub ignore_csum: bool,
-    /// Make UTF-16 error non-fatal
-    pub ignore_utf16_errors: bool
+    /// How to handle partition names which are invalid UTF-16. Defaults to `Utf16Mode::Strict`
+    pub utf16_mode: Utf16Mode,
+    /// Also write a protective MBR to block 0 when writing the GPT
+    pub protective_mbr: bool,
+    /// Require the primary partition table to start at block 2 instead of where the header
+    /// points to
+    pub strict_layout: bool,
+    /// Creates the CRC32 state used for all checksums. Defaults to `crc32`, but can be replaced
+    /// e.g. by an accelerated implementation. Must compute the standard CRC-32 used by GPT.
+    pub crc32: fn() -> Box<dyn Crc32>
 }
 
 impl Default for GPTOptions {
@@ -28,12 +68,56 @@ impl Default for GPTOptions {
         GPTOptions {
             block_size: 512,
             ignore_csum: false,
-            ignore_utf16_errors: false
+            utf16_mode: Utf16Mode::Strict,
+            protective_mbr: false,
+            strict_layout: false,
+            crc32
         }        
     }
 }
 
-#[derive(Debug)]
+impl GPTOptions {
+    /// Create the default options with a different block size
+    ///
+    /// Fails if the block size is not a power of two or smaller than 512
+    pub fn new(block_size: u16) -> Result<GPTOptions, GPTError> {
+        if block_size < 512 || !block_size.is_power_of_two() {
+            return Err(GPTError::new(ErrorType::InvalidBlockSize(block_size)));
+        }
+        Ok(GPTOptions {
+            block_size,
+            ..GPTOptions::default()
+        })
+    }
+
+    /// Checksum a whole buffer with the configured CRC32 implementation
+    fn checksum(&self, buf: &[u8]) -> u32 {
+        let mut crc = (self.crc32)();
+        crc.update(buf);
+        crc.sum()
+    }
+}
+
+/// Incremental CRC32 calculation, so data can be checksummed in chunks
+pub trait Crc32 {
+    /// Feed more data into the checksum
+    fn update(&mut self, buf: &[u8]);
+    /// The checksum of all data fed in so far
+    fn sum(&self) -> u32;
+}
+
+impl Crc32 for CRC32 {
+    fn update(&mut self, buf: &[u8]) {
+        CRC32::update(self, buf)
+    }
+
+    fn sum(&self) -> u32 {
+        !self.getsum()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GPTTable {
     /// Location of the Primary GPT
     primary_gpt: Block,
@@ -47,11 +131,25 @@ pub struct GPTTable {
     gpt_uuid: UUID,
     /// List of partitions
     partitions: Vec<Option<PartitionEntry>>,
+    /// Number of slots in the partition table
+    partition_count: u32,
+    /// Location of the primary partition table. Usually block 2, but kept as found on disk. The
+    /// backup partition table is always placed directly before the backup GPT.
+    part_start: Block,
     /// Checksum of data
-    checksum: u32
+    checksum: u32,
+    /// Checksum of the partition table
+    part_checksum: u32,
+    /// Whether the table was loaded from the backup GPT
+    loaded_from_backup: bool,
+    /// The header as it was loaded, with the checksum zeroed. Reserved bytes are written back
+    /// from here, so they survive loading and writing a table.
+    raw_header: Vec<u8>
 }
 
-#[derive(Debug, Clone)]
+/// Partition entries are compared and hashed by all of their fields
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PartitionEntry {
     /// The type UUID of the partition
     pub part_type: UUID,
@@ -63,7 +161,8 @@ pub struct PartitionEntry {
     pub end: Block,
     /// The flags of the partition
     pub flags: u64,
-    /// The human readable name of the partition. At most 19 chars.
+    /// The human readable name of the partition. At most 36 UTF-16 code units, longer names
+    /// are truncated when writing. Use `set_name` to validate the length.
     pub name: String
 }
 
@@ -79,6 +178,278 @@ impl PartitionEntry {
             name: String::new()
         }
     }
+
+    /// Set the name of the partition
+    ///
+    /// Fails if the name is longer than 36 UTF-16 code units
+    pub fn set_name(&mut self, name: &str) -> Result<(), GPTError> {
+        if name.encode_utf16().count() > 36 {
+            return Err(GPTError::new(ErrorType::NameTooLong));
+        }
+        self.name = String::from(name);
+        Ok(())
+    }
+
+    /// Gets the number of blocks in the partition. Both `start` and `end` are included.
+    ///
+    /// Returns 0 if the partition ends before it starts.
+    pub fn block_count(&self) -> u64 {
+        self.end.0.checked_sub(self.start.0).map_or(0, |len| len.saturating_add(1))
+    }
+
+    /// Gets the size of the partition in bytes
+    pub fn size_bytes(&self, block_size: u16) -> u64 {
+        Block(self.block_count()).to_bytes(block_size)
+    }
+
+    /// Is the partition required for the platform to function
+    pub fn is_required(&self) -> bool {
+        self.flags & FLAG_REQUIRED != 0
+    }
+
+    /// Mark the partition as required for the platform to function
+    pub fn set_required(&mut self, value: bool) {
+        self.set_flag(FLAG_REQUIRED, value);
+    }
+
+    /// Should the firmware refrain from providing block IO for this partition
+    pub fn is_no_block_io(&self) -> bool {
+        self.flags & FLAG_NO_BLOCK_IO != 0
+    }
+
+    /// Tell the firmware not to provide block IO for this partition
+    pub fn set_no_block_io(&mut self, value: bool) {
+        self.set_flag(FLAG_NO_BLOCK_IO, value);
+    }
+
+    /// Is the partition marked as bootable for legacy BIOS
+    pub fn is_legacy_bootable(&self) -> bool {
+        self.flags & FLAG_LEGACY_BOOTABLE != 0
+    }
+
+    /// Mark the partition as bootable for legacy BIOS
+    pub fn set_legacy_bootable(&mut self, value: bool) {
+        self.set_flag(FLAG_LEGACY_BOOTABLE, value);
+    }
+
+    /// Gets the flags 48 to 63, whose meaning depends on the partition type
+    pub fn type_specific_bits(&self) -> u16 {
+        (self.flags >> TYPE_SPECIFIC_SHIFT) as u16
+    }
+
+    /// Sets the flags 48 to 63, whose meaning depends on the partition type
+    pub fn set_type_specific_bits(&mut self, bits: u16) {
+        self.flags = (self.flags & !(0xFFFF << TYPE_SPECIFIC_SHIFT)) | ((bits as u64) << TYPE_SPECIFIC_SHIFT);
+    }
+
+    fn set_flag(&mut self, flag: u64, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    /// Checks whether the block ranges of two partitions intersect
+    pub fn overlaps(&self, other: &PartitionEntry) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+/// How to export a GPT to a MBR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MbrExportMode {
+    /// A protective MBR with a single partition of type 0xEE covering the whole disk
+    Protective,
+    /// A hybrid MBR mirroring up to three GPT partitions, given by their slot ids, alongside
+    /// a partition of type 0xEE covering the GPT itself
+    Hybrid(Vec<u64>)
+}
+
+/// Result of checking the checksums of both copies of a GPT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GptValidation {
+    /// The header of the primary GPT is intact
+    pub primary_header_ok: bool,
+    /// The partition table of the primary GPT is intact
+    pub primary_array_ok: bool,
+    /// The header of the backup GPT is intact
+    pub backup_header_ok: bool,
+    /// The partition table of the backup GPT is intact
+    pub backup_array_ok: bool
+}
+
+impl GptValidation {
+    /// Returns true if both copies of the GPT are intact
+    pub fn is_ok(&self) -> bool {
+        self.primary_header_ok && self.primary_array_ok && self.backup_header_ok && self.backup_array_ok
+    }
+}
+
+/// Result of comparing the headers of the primary and the backup GPT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrossValidation {
+    /// Both headers agree on the location of the primary and the backup header
+    pub locations_match: bool,
+    /// Both headers contain the same disk GUID
+    pub disk_guid_match: bool,
+    /// Both headers contain the same usable range
+    pub usable_range_match: bool
+}
+
+impl CrossValidation {
+    /// Returns true if the headers of both copies agree
+    pub fn is_ok(&self) -> bool {
+        self.locations_match && self.disk_guid_match && self.usable_range_match
+    }
+}
+
+/// A change of a partition slot between two tables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionChange {
+    /// The slot was empty and now contains a partition
+    Added(usize, PartitionEntry),
+    /// The slot contained a partition and is now empty
+    Removed(usize, PartitionEntry),
+    /// The partition in the slot changed. Contains the old and the new partition.
+    Modified(usize, PartitionEntry, PartitionEntry)
+}
+
+/// Changes of the header fields between two tables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderDiff {
+    /// The old and new disk GUID, if it changed
+    pub disk_guid: Option<(UUID, UUID)>,
+    /// The old and new first and last usable block, if they changed
+    pub usable_range: Option<((Block, Block), (Block, Block))>
+}
+
+impl HeaderDiff {
+    /// Returns true if none of the header fields changed
+    pub fn is_empty(&self) -> bool {
+        self.disk_guid.is_none() && self.usable_range.is_none()
+    }
+}
+
+/// Summary of the space usage of a disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Number of blocks in the usable range
+    pub total_usable_blocks: u64,
+    /// Number of usable blocks covered by partitions
+    pub used_blocks: u64,
+    /// Number of usable blocks not covered by partitions
+    pub free_blocks: u64,
+    /// Size of the largest free region in blocks
+    pub largest_free_blocks: u64,
+    /// Number of free regions
+    pub fragment_count: usize
+}
+
+/// Two tables are equal if they have the same usable range, disk GUID and partitions. As the
+/// disk GUID is compared, tables of different disks are never equal, even if their layout is.
+/// Checksums are ignored.
+impl PartialEq for GPTTable {
+    fn eq(&self, other: &GPTTable) -> bool {
+        self.first_usable == other.first_usable
+            && self.last_usable == other.last_usable
+            && self.gpt_uuid == other.gpt_uuid
+            && self.partitions == other.partitions
+    }
+}
+
+impl fmt::Display for PartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\" (", self.name)?;
+        match types::name_for_type(&self.part_type) {
+            Some(name) => write!(f, "{}", name)?,
+            None => write!(f, "{}", self.part_type)?
+        }
+        write!(f, ") blocks {}-{} ({} blocks)", self.start.0, self.end.0, self.block_count())
+    }
+}
+
+/// Builder for partition entries
+///
+/// A random PARTUUID is generated unless one is set explicitly.
+#[derive(Debug, Clone)]
+pub struct PartitionEntryBuilder {
+    part_type: UUID,
+    part_id: Option<UUID>,
+    start: Block,
+    end: Block,
+    flags: u64,
+    name: String
+}
+
+impl PartitionEntryBuilder {
+    /// Creates a new builder for a partition of type nil covering only block 0
+    pub fn new() -> PartitionEntryBuilder {
+        PartitionEntryBuilder {
+            part_type: UUID::nil(),
+            part_id: None,
+            start: Block(0),
+            end: Block(0),
+            flags: 0,
+            name: String::new()
+        }
+    }
+
+    /// Sets the type UUID of the partition
+    pub fn part_type(mut self, part_type: UUID) -> PartitionEntryBuilder {
+        self.part_type = part_type;
+        self
+    }
+
+    /// Sets the PARTUUID of the partition
+    pub fn part_id(mut self, part_id: UUID) -> PartitionEntryBuilder {
+        self.part_id = Some(part_id);
+        self
+    }
+
+    /// Sets the first and the last block of the partition
+    pub fn range(mut self, start: Block, end: Block) -> PartitionEntryBuilder {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    /// Sets the flags of the partition
+    pub fn flags(mut self, flags: u64) -> PartitionEntryBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// Sets the name of the partition
+    pub fn name(mut self, name: &str) -> PartitionEntryBuilder {
+        self.name = String::from(name);
+        self
+    }
+
+    /// Builds the partition entry
+    ///
+    /// Fails if the name is too long or the partition ends before it starts
+    pub fn build(self) -> Result<PartitionEntry, GPTError> {
+        if self.start > self.end {
+            return Err(GPTError::new(ErrorType::InvalidRange));
+        }
+        let mut part = PartitionEntry {
+            part_type: self.part_type,
+            part_id: self.part_id.unwrap_or_else(UUID::new_v4),
+            start: self.start,
+            end: self.end,
+            flags: self.flags,
+            name: String::new()
+        };
+        part.set_name(&self.name)?;
+        Ok(part)
+    }
+}
+
+impl Default for PartitionEntryBuilder {
+    fn default() -> PartitionEntryBuilder {
+        PartitionEntryBuilder::new()
+    }
 }
 
 #[derive(Debug)]
@@ -87,17 +458,51 @@ pub enum ErrorType {
     NoTable,
     /// The checksum of both the primary and the backup GPT was incorrect
     ChecksumError,
+    /// The checksum of the GPT header is incorrect
+    HeaderChecksumError,
+    /// The checksum of the partition table is incorrect
+    PartitionArrayChecksumError,
     /// The Version of the GPT is incompatible with this implementation
     InvalidVersion,
     /// The header in itself is invalid
     InvalidHeader,
+    /// The stream ended before the GPT was read completely
+    TruncatedStream,
+    /// The header size is not supported by this implementation
+    UnsupportedHeaderSize(u32),
+    /// The partition entry size is not supported by this implementation
+    UnsupportedEntrySize(u32),
+    /// The partition table of the primary GPT does not start at block 2
+    InvalidPartitionTableStart(Block),
     /// During parsing or writing a GPT an IO Error occured
     IOError(IOError),
     /// One of the UUIDs is not valid
     UUIDError(UUIDError),
     /// One of the strings is invalid UTF-16
     UTF16Error,
-    InvalidID
+    InvalidID,
+    /// The disk is too small to hold a GPT
+    DiskTooSmall,
+    /// The partition count is not a multiple of 4 or too small for the used partitions
+    InvalidPartitionCount(u32),
+    /// The partition overlaps with another partition
+    Overlap,
+    /// The partition lies outside of the usable area of the disk
+    OutOfBounds,
+    /// There is no free region large enough for the partition
+    NoSpace,
+    /// All partition slots are in use
+    TableFull,
+    /// The partition name does not fit into 36 UTF-16 code units
+    NameTooLong,
+    /// The partition ends before it starts
+    InvalidRange,
+    /// The partition can not be described in a MBR, as it exceeds 2^32 blocks
+    ExceedsMbrLimit,
+    /// Reading or writing the MBR failed
+    MBRError(MBRError),
+    /// The block size is not a power of two or smaller than 512
+    InvalidBlockSize(u16)
 }
 
 #[derive(Debug)]
@@ -109,14 +514,31 @@ pub struct GPTError {
 impl GPTError {
     fn new(t: ErrorType) -> GPTError {
         let desc = String::from(match &t {
-            &ErrorType::NoTable => String::from("No GPT found"),
-            &ErrorType::ChecksumError => String::from("GPT corrupt"),
-            &ErrorType::InvalidVersion => String::from("Invalid GPT Version"),
-            &ErrorType::InvalidHeader => String::from("Invalid GPT Header"),
-            &ErrorType::UTF16Error => String::from("Encoding Error in GPT: Invalid UTF-16"),
-            &ErrorType::InvalidID => String::from("Invalid ID"),
-            &ErrorType::IOError(ref e) => format!("IO Error while processing GPT: {}", e.description()),
-            &ErrorType::UUIDError(ref e) => format!("Invalid UUID: {}", e.description())
+            ErrorType::NoTable => String::from("No GPT found"),
+            ErrorType::ChecksumError => String::from("GPT corrupt"),
+            ErrorType::HeaderChecksumError => String::from("GPT header corrupt"),
+            ErrorType::PartitionArrayChecksumError => String::from("GPT partition table corrupt"),
+            ErrorType::InvalidVersion => String::from("Invalid GPT Version"),
+            ErrorType::InvalidHeader => String::from("Invalid GPT Header"),
+            ErrorType::TruncatedStream => String::from("Unexpected end of stream while reading GPT"),
+            ErrorType::UnsupportedHeaderSize(size) => format!("Unsupported GPT header size: {}", size),
+            ErrorType::UnsupportedEntrySize(size) => format!("Unsupported partition entry size: {}", size),
+            ErrorType::InvalidPartitionTableStart(block) => format!("Invalid start of partition table: block {}", block.0),
+            ErrorType::UTF16Error => String::from("Encoding Error in GPT: Invalid UTF-16"),
+            ErrorType::InvalidID => String::from("Invalid ID"),
+            ErrorType::DiskTooSmall => String::from("Disk too small for GPT"),
+            ErrorType::InvalidPartitionCount(count) => format!("Invalid partition count: {}", count),
+            ErrorType::Overlap => String::from("Partition overlaps with another partition"),
+            ErrorType::OutOfBounds => String::from("Partition lies outside of the usable area"),
+            ErrorType::NoSpace => String::from("Not enough free space for partition"),
+            ErrorType::TableFull => String::from("All partition slots are in use"),
+            ErrorType::NameTooLong => String::from("Partition name too long"),
+            ErrorType::InvalidRange => String::from("Partition ends before it starts"),
+            ErrorType::ExceedsMbrLimit => String::from("Partition exceeds the limits of MBR"),
+            ErrorType::IOError(e) => format!("IO Error while processing GPT: {}", e.description()),
+            ErrorType::UUIDError(e) => format!("Invalid UUID: {}", e.description()),
+            ErrorType::MBRError(e) => format!("Error in MBR: {}", e),
+            ErrorType::InvalidBlockSize(size) => format!("Invalid block size: {}", size)
         });
         GPTError {
             error_type: t,
@@ -125,27 +547,74 @@ impl GPTError {
     }
 }
 
+impl GPTError {
+    /// Gets the kind of error that occured
+    pub fn error_type(&self) -> &ErrorType {
+        &self.error_type
+    }
+
+    /// Returns true if a checksum of the header or the partition table was incorrect
+    pub fn is_checksum_error(&self) -> bool {
+        matches!(self.error_type, ErrorType::ChecksumError | ErrorType::HeaderChecksumError | ErrorType::PartitionArrayChecksumError)
+    }
+}
+
 impl From<IOError> for GPTError {
     fn from(err: IOError) -> GPTError {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            // Short reads mean the GPT is cut off, rather than a problem with the device
+            return GPTError::new(ErrorType::TruncatedStream);
+        }
         GPTError::new(ErrorType::IOError(err))
     }
 }
 
+impl From<MBRError> for GPTError {
+    fn from(err: MBRError) -> GPTError {
+        match err {
+            MBRError::IOError(e) => GPTError::from(e),
+            e => GPTError::new(ErrorType::MBRError(e))
+        }
+    }
+}
+
 impl From<UUIDError> for GPTError {
     fn from(err: UUIDError) -> GPTError {
         GPTError::new(ErrorType::UUIDError(err))
     }
 }
 
+impl From<GPTError> for IOError {
+    fn from(err: GPTError) -> IOError {
+        let kind = match err.error_type {
+            ErrorType::IOError(e) => return e,
+            ErrorType::NoTable => ErrorKind::NotFound,
+            ErrorType::TruncatedStream => ErrorKind::UnexpectedEof,
+            ErrorType::ChecksumError | ErrorType::HeaderChecksumError
+                | ErrorType::PartitionArrayChecksumError | ErrorType::InvalidVersion | ErrorType::InvalidHeader
+                | ErrorType::UnsupportedHeaderSize(_) | ErrorType::UnsupportedEntrySize(_)
+                | ErrorType::InvalidPartitionTableStart(_) | ErrorType::UUIDError(_)
+                | ErrorType::UTF16Error | ErrorType::MBRError(_) => ErrorKind::InvalidData,
+            ErrorType::InvalidID | ErrorType::DiskTooSmall | ErrorType::InvalidPartitionCount(_)
+                | ErrorType::Overlap | ErrorType::OutOfBounds | ErrorType::NameTooLong
+                | ErrorType::InvalidRange | ErrorType::ExceedsMbrLimit
+                | ErrorType::InvalidBlockSize(_) => ErrorKind::InvalidInput,
+            ErrorType::NoSpace | ErrorType::TableFull => ErrorKind::Other
+        };
+        IOError::new(kind, err)
+    }
+}
+
 impl Error for GPTError {
     fn description(&self) -> &str {
         &self.desc
     }
 
-    fn cause(&self) -> Option<&Error> {
+    fn cause(&self) -> Option<&dyn Error> {
         match &self.error_type {
-            &ErrorType::IOError(ref e) => Some(e),
-            &ErrorType::UUIDError(ref e) => Some(e),
+            ErrorType::IOError(e) => Some(e),
+            ErrorType::UUIDError(e) => Some(e),
+            ErrorType::MBRError(e) => Some(e),
             _ => None
         }
     }
@@ -159,24 +628,117 @@ impl fmt::Display for GPTError {
 
 impl GPTTable {
 
+    /// Create a new empty GPT with `part_count` partition slots for a disk of `volume_size`
+    /// bytes. Panics if the disk is too small to hold the GPT.
     pub fn new(volume_size: u64, part_count: u64, options: &GPTOptions) -> GPTTable {
-        let (block, offset) = Block::from_bytes_offset(volume_size, options.block_size);
-        let last_block = if offset != 0 {
-            block - Block(1)
-        } else {
-            block
-        };
-        let first_usable = Block(1) + GPTTable::ptable_len(part_count, options);
+        let (disk_blocks, _) = Block::from_bytes_offset(volume_size, options.block_size);
+        GPTTable::with_layout(disk_blocks.0, part_count, options)
+    }
+
+    /// Create a new empty GPT with 128 partition slots for a disk of `disk_blocks` blocks
+    ///
+    /// The primary GPT is placed at block 1 and the backup GPT at the last block of the disk.
+    pub fn create(disk_blocks: u64, options: &GPTOptions) -> Result<GPTTable, GPTError> {
+        let ptable_len = GPTTable::ptable_len(DEFAULT_PART_COUNT, options);
+        // Protective MBR, two headers, two partition tables and at least one usable block
+        if disk_blocks < 4 + 2 * ptable_len.0 {
+            return Err(GPTError::new(ErrorType::DiskTooSmall));
+        }
+        Ok(GPTTable::with_layout(disk_blocks, DEFAULT_PART_COUNT, options))
+    }
+
+    fn with_layout(disk_blocks: u64, part_count: u64, options: &GPTOptions) -> GPTTable {
+        let ptable_len = GPTTable::ptable_len(part_count, options);
+        let backup_gpt = Block(disk_blocks - 1);
         let mut parts = Vec::with_capacity(part_count as usize);
         parts.resize(part_count as usize, None);
         GPTTable {
             primary_gpt: Block(1),
-            backup_gpt: last_block,
-            first_usable,
-            last_usable: last_block - GPTTable::ptable_len(part_count, options),
+            backup_gpt,
+            first_usable: Block(2) + ptable_len,
+            last_usable: backup_gpt - ptable_len - Block(1),
             gpt_uuid: UUID::new_v4(),
             partitions: parts,
+            partition_count: part_count as u32,
+            part_start: Block(2),
             checksum: 0,
+            part_checksum: 0,
+            loaded_from_backup: false,
+            raw_header: vec![0u8; 92]
+        }
+    }
+
+    /// Convert a MBR to a GPT for a disk of `disk_blocks` blocks
+    ///
+    /// Every primary partition keeps its slot id and gets a random PARTUUID and a type matching
+    /// its MBR type. Extended and protective partitions are not converted. Fails if a partition
+    /// overlaps the space needed for the GPT.
+    pub fn from_mbr(mbr: &MBR, disk_blocks: u64, options: &GPTOptions) -> Result<GPTTable, GPTError> {
+        let mut table = GPTTable::create(disk_blocks, options)?;
+        for (id, p) in mbr.used_partitions() {
+            match p.system_id {
+                0x05 | 0x0F | 0x85 | 0xEE => continue,
+                _ => {}
+            }
+            let part = PartitionEntryBuilder::new()
+                .part_type(types::type_for_mbr_id(p.system_id))
+                .range(Block(p.start_lba as u64), Block((p.start_lba as u64 + p.sector_count as u64).saturating_sub(1)))
+                .build()?;
+            table.set_partition_checked(id as u64, part)?;
+        }
+        Ok(table)
+    }
+
+    /// Remove the GPT from a disk of `disk_blocks` blocks
+    ///
+    /// Zeroes the headers and partition tables of both GPT copies. The location and size of a
+    /// partition table are taken from its header if the header is intact, otherwise the default
+    /// layout with 128 partition slots is assumed. The MBR is zeroed as well, unless `keep_mbr`
+    /// is set.
+    pub fn erase<T: Read + Write + Seek>(disk: &mut T, disk_blocks: u64, keep_mbr: bool, options: &GPTOptions) -> Result<(), GPTError> {
+        let default_len = GPTTable::ptable_len(DEFAULT_PART_COUNT, options);
+        if disk_blocks < 4 + 2 * default_len.0 {
+            return Err(GPTError::new(ErrorType::DiskTooSmall));
+        }
+        let backup_lba = Block(disk_blocks - 1);
+
+        // Locate both partition tables before anything is zeroed
+        let primary = GPTTable::ptable_region(disk, Block(1), (Block(2), default_len), disk_blocks, options)?;
+        let backup = GPTTable::ptable_region(disk, backup_lba, (backup_lba - default_len, default_len), disk_blocks, options)?;
+
+        let zeroes = vec![0u8; options.block_size as usize];
+        let zero_blocks = |write: &mut T, start: Block, count: Block| -> IOResult<()> {
+            write.seek(SeekFrom::Start(start.to_bytes(options.block_size)))?;
+            for _ in 0..count.0 {
+                write.write_all(&zeroes)?;
+            }
+            Ok(())
+        };
+
+        if !keep_mbr {
+            zero_blocks(disk, Block(0), Block(1))?;
+        }
+        zero_blocks(disk, Block(1), Block(1))?;
+        zero_blocks(disk, primary.0, primary.1)?;
+        zero_blocks(disk, backup.0, backup.1)?;
+        zero_blocks(disk, backup_lba, Block(1))?;
+        Ok(())
+    }
+
+    /// The start and length of the partition table belonging to the header at `lba`, or
+    /// `default` if there is no intact header pointing to a partition table on the disk
+    fn ptable_region<T: Read + Seek>(read: &mut T, lba: Block, default: (Block, Block), disk_blocks: u64, options: &GPTOptions) -> Result<(Block, Block), GPTError> {
+        let header = match RawHeader::read(read, lba, options)? {
+            Some(header) => header,
+            None => return Ok(default)
+        };
+        if !header.header_ok || header.part_size != 128 || header.part_count > MAX_PART_COUNT {
+            return Ok(default);
+        }
+        let len = GPTTable::ptable_len(header.part_count as u64, options);
+        match header.part_start.checked_add(len) {
+            Some(end) if header.part_start > Block(1) && end.0 <= disk_blocks => Ok((header.part_start, len)),
+            _ => Ok(default)
         }
     }
 
@@ -186,119 +748,335 @@ impl GPTTable {
         read.seek(SeekFrom::Start(block_size as u64))?;
 
         let mut buf = [0u8; 8];
-        read.read(&mut buf)?;
+        match read.read_exact(&mut buf) {
+            // Too small to hold a GPT
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            r => r?
+        }
 
         Ok(buf == GPT_MAGIC)
     }
 
     /// Load a GPT from file or stream
+    ///
+    /// If the checksum of the primary GPT is incorrect, the backup GPT is loaded instead.
     pub fn load<T: Read + Seek>(read: &mut T, options: &GPTOptions) -> Result<GPTTable, GPTError> {
+        match GPTTable::load_copy(read, Block(1), options, true) {
+            Err(err) => {
+                if !err.is_checksum_error() {
+                    return Err(err);
+                }
+                let backup = GPTTable::find_backup(read, options)?;
+                match GPTTable::load_copy(read, backup, options, false) {
+                    Ok(mut table) => {
+                        table.loaded_from_backup = true;
+                        Ok(table)
+                    },
+                    // Both copies are corrupt
+                    Err(ref e) if e.is_checksum_error() => Err(GPTError::new(ErrorType::ChecksumError)),
+                    Err(_) => Err(err)
+                }
+            },
+            r => r
+        }
+    }
+
+    /// Load the backup GPT from the last block of a disk with `disk_blocks` blocks, without
+    /// looking at the primary GPT at all
+    ///
+    /// Useful to recover a disk whose primary GPT is damaged beyond being recognized. Both
+    /// checksums of the backup are verified unless disabled in `options`.
+    pub fn load_from_backup<T: Read + Seek>(read: &mut T, disk_blocks: u64, options: &GPTOptions) -> Result<GPTTable, GPTError> {
+        if disk_blocks < 2 {
+            return Err(GPTError::new(ErrorType::DiskTooSmall));
+        }
+        let mut table = GPTTable::load_copy(read, Block(disk_blocks - 1), options, false)?;
+        table.loaded_from_backup = true;
+        Ok(table)
+    }
+
+    /// Load the primary GPT from a stream which can not seek, e.g. a pipe
+    ///
+    /// Only the start of the stream up to the end of the primary partition table is read, so
+    /// the backup GPT is neither validated nor used if the primary GPT is corrupt.
+    /// `disk_blocks_hint` is the size of the disk in blocks, a header pointing to a partition
+    /// table beyond it is rejected. The header is verified before the partition table is read.
+    pub fn load_buffered<R: Read>(read: &mut R, disk_blocks_hint: u64, options: &GPTOptions) -> Result<GPTTable, GPTError> {
+        let block_size = options.block_size as usize;
+        let mut buf = vec![0u8; 2 * block_size];
+        read.read_exact(&mut buf)?;
+        if buf[block_size..block_size + 8] != GPT_MAGIC {
+            return Err(GPTError::new(ErrorType::NoTable));
+        }
+
+        // Check everything the size of the partition table depends on before reading it, so a
+        // corrupt header can not make us read most of the disk
+        let header = match RawHeader::read(&mut Cursor::new(&buf[..]), Block(1), options)? {
+            Some(header) => header,
+            None => return Err(GPTError::new(ErrorType::NoTable))
+        };
+        if !header.header_ok && !options.ignore_csum {
+            return Err(GPTError::new(ErrorType::HeaderChecksumError));
+        }
+        if header.part_size != 128 {
+            return Err(GPTError::new(ErrorType::UnsupportedEntrySize(header.part_size)));
+        }
+        if header.part_count > MAX_PART_COUNT {
+            return Err(GPTError::new(ErrorType::InvalidPartitionCount(header.part_count)));
+        }
+        // The primary partition table lies between the header and the first usable block
+        let part_bytes = header.part_count as u64 * 128;
+        let part_end = match header.part_start.checked_add(Block(part_bytes.div_ceil(block_size as u64))) {
+            Some(end) if header.part_start > Block(1) && end <= header.first_usable => end,
+            _ => return Err(GPTError::new(ErrorType::InvalidPartitionTableStart(header.part_start)))
+        };
+        if part_end.0 > disk_blocks_hint {
+            return Err(GPTError::new(ErrorType::InvalidHeader));
+        }
+
+        let len = buf.len();
+        buf.resize(cmp::max(len, part_end.to_bytes(options.block_size) as usize), 0u8);
+        read.read_exact(&mut buf[len..])?;
+
+        GPTTable::load_copy(&mut Cursor::new(buf), Block(1), options, true)
+    }
+
+    /// Check the checksums of both copies of a GPT without loading the partitions
+    ///
+    /// The partition table of a copy is only considered intact if its header is, as the
+    /// location and checksum of the partition table are taken from the header.
+    pub fn validate<T: Read + Seek>(read: &mut T, options: &GPTOptions) -> Result<GptValidation, GPTError> {
+        let primary = RawHeader::read(read, Block(1), options)?;
+        let backup_lba = GPTTable::find_backup(read, options)?;
+        let backup = RawHeader::read(read, backup_lba, options)?;
+        if primary.is_none() && backup.is_none() {
+            return Err(GPTError::new(ErrorType::NoTable));
+        }
+
+        let (primary_header_ok, primary_array_ok) = match primary {
+            Some(header) => (header.header_ok, header.partitions_ok(read, options)?),
+            None => (false, false)
+        };
+        let (backup_header_ok, backup_array_ok) = match backup {
+            Some(header) => (header.header_ok, header.partitions_ok(read, options)?),
+            None => (false, false)
+        };
+
+        Ok(GptValidation {
+            primary_header_ok,
+            primary_array_ok,
+            backup_header_ok,
+            backup_array_ok
+        })
+    }
+
+    /// Compare the headers of the primary and the backup GPT
+    ///
+    /// This detects a backup GPT which has a valid checksum but belongs to a different or older
+    /// table. Fails with `NoTable` if one of the copies is missing.
+    pub fn cross_validate<T: Read + Seek>(read: &mut T, options: &GPTOptions) -> Result<CrossValidation, GPTError> {
+        let backup_lba = GPTTable::find_backup(read, options)?;
+        let primary = RawHeader::read(read, Block(1), options)?;
+        let backup = RawHeader::read(read, backup_lba, options)?;
+        let (primary, backup) = match (primary, backup) {
+            (Some(primary), Some(backup)) => (primary, backup),
+            _ => return Err(GPTError::new(ErrorType::NoTable))
+        };
+
+        Ok(CrossValidation {
+            locations_match: primary.my_lba == backup.alternate_lba && primary.alternate_lba == backup.my_lba,
+            disk_guid_match: primary.disk_guid == backup.disk_guid,
+            usable_range_match: primary.first_usable == backup.first_usable && primary.last_usable == backup.last_usable
+        })
+    }
+
+    /// Check whether the backup GPT on disk has the same partitions and disk GUID as the
+    /// primary GPT
+    ///
+    /// Only the headers and the raw partition arrays are read, so this is cheap enough to
+    /// decide whether `repair_backup` is needed. A backup with a corrupt header never matches.
+    /// Fails with `NoTable` if one of the copies is missing, or with `HeaderChecksumError` if
+    /// the primary header is corrupt.
+    pub fn backup_matches_primary<T: Read + Seek>(read: &mut T, options: &GPTOptions) -> Result<bool, GPTError> {
+        let backup_lba = GPTTable::find_backup(read, options)?;
+        let primary = RawHeader::read(read, Block(1), options)?;
+        let backup = RawHeader::read(read, backup_lba, options)?;
+        let (primary, backup) = match (primary, backup) {
+            (Some(primary), Some(backup)) => (primary, backup),
+            _ => return Err(GPTError::new(ErrorType::NoTable))
+        };
+
+        if !primary.header_ok {
+            return Err(GPTError::new(ErrorType::HeaderChecksumError));
+        }
+        if !backup.header_ok || primary.disk_guid != backup.disk_guid {
+            return Ok(false);
+        }
+        let primary_crc = options.checksum(&primary.read_partitions(read, options)?);
+        let backup_crc = options.checksum(&backup.read_partitions(read, options)?);
+        Ok(primary_crc == backup_crc)
+    }
+
+    /// Read the raw bytes `write` would overwrite when writing this table
+    ///
+    /// Returns (offset, bytes) pairs for the regions listed by `write_plan`, with adjacent
+    /// regions merged. Writing each of them back at its offset undoes writing this table,
+    /// including the backup GPT at the end of the disk. Fails if the stream is too short to hold
+    /// this table.
+    ///
+    /// This is a method rather than a function of the stream alone, as the overwritten regions
+    /// depend on the table being written: the backup GPT of a cloned table may end up at a
+    /// different block than the one on disk, and the partition tables may have a different size.
+    /// The regions are returned separately, as a single buffer would have to span the whole disk.
+    pub fn snapshot_raw<T: Read + Seek>(&self, read: &mut T, options: &GPTOptions) -> Result<Vec<(u64, Vec<u8>)>, GPTError> {
+        let mut plan = self.write_plan(options);
+        plan.sort();
+
+        let mut regions: Vec<(u64, u64)> = Vec::new();
+        for (offset, len) in plan {
+            if let Some(last) = regions.last_mut() {
+                if offset <= last.0 + last.1 {
+                    last.1 = cmp::max(last.1, offset + len - last.0);
+                    continue;
+                }
+            }
+            regions.push((offset, len));
+        }
+
+        let mut snapshot = Vec::with_capacity(regions.len());
+        for (offset, len) in regions {
+            let mut buf = vec![0u8; len as usize];
+            read.seek(SeekFrom::Start(offset))?;
+            read.read_exact(&mut buf)?;
+            snapshot.push((offset, buf));
+        }
+        Ok(snapshot)
+    }
+
+    /// Locate the backup GPT. Uses the location recorded in the primary header if it is
+    /// plausible, otherwise the last block of the stream.
+    fn find_backup<T: Read + Seek>(read: &mut T, options: &GPTOptions) -> Result<Block, GPTError> {
+        let block_size = options.block_size as u64;
+        let last_block = Block((read.seek(SeekFrom::End(0))? / block_size).saturating_sub(1));
+
+        read.seek(SeekFrom::Start(block_size + 32))?;
+        let recorded = Block(read.read_u64::<LittleEndian>()?);
+
+        if recorded > Block(1) && recorded <= last_block {
+            Ok(recorded)
+        } else {
+            Ok(last_block)
+        }
+    }
+
+    /// Load a single copy of the GPT whose header is located at `lba`
+    fn load_copy<T: Read + Seek>(read: &mut T, lba: Block, options: &GPTOptions, primary: bool) -> Result<GPTTable, GPTError> {
 
         let block_size = options.block_size;
 
-        // Actually go to the start of the GPT
-        read.seek(SeekFrom::Start(block_size as u64))?;
+        // Parse the header through a window on its block, so it can not read past it
+        let mut header = Window::new(read, lba.to_bytes(block_size), block_size as u64);
 
         let mut buf = [0u8; 8];
-        read.read(&mut buf)?;
+        header.read_exact(&mut buf)?;
         if buf != GPT_MAGIC {
             return Err(GPTError::new(ErrorType::NoTable));
         }
 
         let mut buf = [0u8; 4];
-        read.read(&mut buf)?;
+        header.read_exact(&mut buf)?;
         if buf != [0x00, 0x00, 0x01, 0x00] {
-            println!("Invalid header version");
             return Err(GPTError::new(ErrorType::InvalidVersion));
         }
 
-        let hlen = read.read_u32::<LittleEndian>()?;
+        let hlen = header.read_u32::<LittleEndian>()?;
 
-        if hlen != 92 {
-            println!("Header length is not 92");
-            return Err(GPTError::new(ErrorType::InvalidHeader));
+        // Newer revisions may append fields, which are reserved for us
+        if hlen < 92 || hlen > block_size as u32 {
+            return Err(GPTError::new(ErrorType::UnsupportedHeaderSize(hlen)));
         }
 
-        // FIXME: ignoring checksum for now
-        let crc = read.read_u32::<LittleEndian>()?;
+        // Verified once the whole header has been read
+        let crc = header.read_u32::<LittleEndian>()?;
 
         // Reserved. Let's ignore it.
-        read.read_i32::<LittleEndian>()?;
+        header.read_i32::<LittleEndian>()?;
         
-        let mypos = Block(read.read_u64::<LittleEndian>()?);
+        let mypos = Block(header.read_u64::<LittleEndian>()?);
 
-        let otherpos = Block(read.read_u64::<LittleEndian>()?);
+        let otherpos = Block(header.read_u64::<LittleEndian>()?);
 
-        let first_usable = Block(read.read_u64::<LittleEndian>()?);
+        let first_usable = Block(header.read_u64::<LittleEndian>()?);
 
-        let last_usable = Block(read.read_u64::<LittleEndian>()?);
+        let last_usable = Block(header.read_u64::<LittleEndian>()?);
 
-        let uuid = read_uuid(read)?;
+        let uuid = read_uuid(&mut header)?;
 
-        let part_start = Block(read.read_u64::<LittleEndian>()?);
-        if part_start != Block(2) {
-            // In primary GPT this is ALWAYS 2
-            println!("Invalid start of partition table");
-            return Err(GPTError::new(ErrorType::InvalidHeader));
+        let part_start = Block(header.read_u64::<LittleEndian>()?);
+        if primary && (part_start <= mypos || (options.strict_layout && part_start != Block(2))) {
+            // The primary partition table has to follow the header, usually at block 2
+            return Err(GPTError::new(ErrorType::InvalidPartitionTableStart(part_start)));
         }
 
-        let part_count = read.read_u32::<LittleEndian>()?;
+        let part_count = header.read_u32::<LittleEndian>()?;
+        if part_count > MAX_PART_COUNT {
+            return Err(GPTError::new(ErrorType::InvalidPartitionCount(part_count)));
+        }
 
-        let part_size = read.read_u32::<LittleEndian>()?;
+        let part_size = header.read_u32::<LittleEndian>()?;
         if part_size != 128 {
-            println!("Invalid partition table entry size");
-            return Err(GPTError::new(ErrorType::InvalidHeader));
+            return Err(GPTError::new(ErrorType::UnsupportedEntrySize(part_size)));
         }
 
-        let part_checksum = read.read_u32::<LittleEndian>()?;
+        let part_checksum = header.read_u32::<LittleEndian>()?;
+
+        // Keep the whole header around, including the reserved parts
+        header.seek(SeekFrom::Start(0))?;
+        let mut raw_header = vec![0u8; hlen as usize];
+        header.read_exact(&mut raw_header)?;
+        // Zero out checksum field
+        cp(&[0x00, 0x00, 0x00, 0x00], &mut raw_header[16..20]);
 
         if !options.ignore_csum {
             // Time to verify checksum
-            read.seek(SeekFrom::Start(block_size as u64))?;
-            let mut buf = Vec::new();
-            buf.resize(hlen as usize, 0u8);
-            read.read(&mut buf)?;
-            // Zero out checksum field
-            cp(&[0x00, 0x00, 0x00, 0x00], &mut buf[16..20]);
-
-            let csum = CRC32::new().checksum(&buf);
+            let csum = options.checksum(&raw_header);
 
             if csum != crc {
-                return Err(GPTError::new(ErrorType::ChecksumError));
+                return Err(GPTError::new(ErrorType::HeaderChecksumError));
             }
 
             // Time to checksum the partition table
-            read.seek(SeekFrom::Start(part_start.to_bytes(block_size)))?;
-            
+            let mut array = Window::new(read, part_start.to_bytes(block_size), part_size as u64 * part_count as u64);
             let mut buf = Vec::new();
             buf.resize(part_size as usize * part_count as usize, 0u8);
-            read.read(&mut buf)?;
+            array.read_exact(&mut buf)?;
 
-            let csum = CRC32::new().checksum(&buf);
+            let csum = options.checksum(&buf);
             if csum != part_checksum {
-                return Err(GPTError::new(ErrorType::ChecksumError));
+                return Err(GPTError::new(ErrorType::PartitionArrayChecksumError));
             }
         }
 
         // Okay, Lets read the actual partition table
-        
-        read.seek(SeekFrom::Start(part_start.to_bytes(block_size)))?;
+        let mut array = Window::new(read, part_start.to_bytes(block_size), part_size as u64 * part_count as u64);
 
         // Stuff might break on 64 bit once we get huuuuuge hard disks.
         // But eh, 32 bit will be gone by then anyways
         let mut partitions = Vec::with_capacity(part_count as usize);
 
         for _ in 0..part_count {
-            let part_type = read_uuid(read)?;
-            let part_id = read_uuid(read)?;
-            let part_start = Block(read.read_u64::<LittleEndian>()?);
-            let part_end = Block(read.read_u64::<LittleEndian>()?);
-            let part_flags = read.read_u64::<LittleEndian>()?;
-            let part_label = read_utf16_le(read, options.ignore_utf16_errors)?;
+            let part_type = read_uuid(&mut array)?;
+            let part_id = read_uuid(&mut array)?;
+            let part_start = Block(array.read_u64::<LittleEndian>()?);
+            let part_end = Block(array.read_u64::<LittleEndian>()?);
+            let part_flags = array.read_u64::<LittleEndian>()?;
+            let part_label = read_utf16_le(&mut array, options.utf16_mode)?;
 
 
             if part_type.is_nil() {
                 partitions.push(None)
+            } else if part_end < part_start {
+                return Err(GPTError::new(ErrorType::InvalidRange));
             } else {
                 partitions.push(Some(PartitionEntry {
                     part_type,
@@ -312,125 +1090,512 @@ impl GPTTable {
         }
 
 
+        let (primary_gpt, backup_gpt) = if primary {
+            (mypos, otherpos)
+        } else {
+            (otherpos, mypos)
+        };
+
         Ok(GPTTable {
-            primary_gpt: mypos,
-            backup_gpt: otherpos,
+            primary_gpt,
+            backup_gpt,
             first_usable,
             last_usable,
             gpt_uuid: uuid,
             partitions,
-            checksum: crc
+            partition_count: part_count,
+            // The backup header does not tell where the primary partition table is
+            part_start: if primary { part_start } else { Block(2) },
+            checksum: crc,
+            part_checksum,
+            loaded_from_backup: false,
+            raw_header
         })
     }
 
     /// Write a GPT to file. will write both primary and backup
     pub fn write<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
-        self.write_gpt(write, options, true)?;
-        self.write_gpt(write, options, false)?;
+        self.write_with_progress(write, options, |_, _| {})
+    }
+
+    /// Write a GPT to file like `write`, calling `progress` with the number of bytes written so
+    /// far and the total number of bytes after the MBR and each copy of the GPT was written
+    pub fn write_with_progress<W: Write + Seek, F: FnMut(u64, u64)>(&self, write: &mut W, options: &GPTOptions, mut progress: F) -> Result<(), GPTError> {
+        // The plan lists the MBR, followed by header and partition table of both copies
+        let plan = self.write_plan(options);
+        let total = plan.iter().map(|&(_, len)| len).sum();
+        let mut regions = plan.iter().map(|&(_, len)| len);
+        let mut written = 0;
+
+        if options.protective_mbr {
+            self.write_protective_mbr(write)?;
+            written += regions.next().unwrap_or(0);
+            progress(written, total);
+        }
+        self.write_primary_only(write, options)?;
+        written += regions.by_ref().take(2).sum::<u64>();
+        progress(written, total);
+        self.write_backup_only(write, options)?;
+        written += regions.by_ref().take(2).sum::<u64>();
+        progress(written, total);
         Ok(())
     }
 
-    fn write_gpt<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions, primary: bool) -> Result<(), GPTError> {
+    /// Write only the primary GPT, leaving the backup GPT and the MBR untouched
+    pub fn write_primary_only<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
+        self.write_gpt(write, options, true)
+    }
 
-        let mut gpt = Vec::new();
-        gpt.resize(92, 0u8);
+    /// Write only the backup GPT, leaving the primary GPT and the MBR untouched
+    pub fn write_backup_only<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
+        self.write_gpt(write, options, false)
+    }
 
-        let mut cur = Cursor::new(gpt);
+    /// Rewrite only the primary GPT, e.g. after the table was loaded from the backup GPT
+    pub fn repair_primary<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
+        self.write_primary_only(write, options)
+    }
 
-        // Magic Bytes
-        cur.write(&GPT_MAGIC)?;
-        // Revision
-        cur.write(&[0x00, 0x00, 0x01, 0x00])?;
-        // Header size
-        cur.write_u32::<LittleEndian>(92)?;
-        // CRC32 sum - for now 0
-        cur.write_u32::<LittleEndian>(0)?;
-        // Reserved
-        cur.write_i32::<LittleEndian>(0)?;
+    /// Rewrite only the backup GPT, leaving the primary GPT untouched
+    pub fn repair_backup<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
+        self.write_backup_only(write, options)
+    }
 
-        let mypos = if primary {
-            cur.write_u64::<LittleEndian>(self.primary_gpt.0)?;
-            cur.write_u64::<LittleEndian>(self.backup_gpt.0)?;
-            self.primary_gpt
-        } else {
-            cur.write_u64::<LittleEndian>(self.backup_gpt.0)?;
-            cur.write_u64::<LittleEndian>(self.primary_gpt.0)?;
-            self.backup_gpt
+    /// Repair a damaged partition table by copying the raw bytes of the intact one over it
+    ///
+    /// Both headers have to be intact and describe the same partition table, which is the case
+    /// if only the partition table of one copy was damaged. As neither the headers nor the
+    /// partition entries are rewritten, fields not known to this library are preserved. Does
+    /// nothing if both partition tables are intact.
+    pub fn repair_from<W: Write + Seek, R: Read + Seek>(read: &mut R, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
+        let backup_lba = GPTTable::find_backup(read, options)?;
+        let primary = RawHeader::read(read, Block(1), options)?;
+        let backup = RawHeader::read(read, backup_lba, options)?;
+        let (primary, backup) = match (primary, backup) {
+            (Some(primary), Some(backup)) => (primary, backup),
+            _ => return Err(GPTError::new(ErrorType::NoTable))
         };
+        if !primary.header_ok || !backup.header_ok {
+            return Err(GPTError::new(ErrorType::HeaderChecksumError));
+        }
 
-        cur.write_u64::<LittleEndian>(self.first_usable.0)?;
-        cur.write_u64::<LittleEndian>(self.last_usable.0)?;
+        let (good, bad) = match (primary.partitions_ok(read, options)?, backup.partitions_ok(read, options)?) {
+            (true, true) => return Ok(()),
+            (true, false) => (primary, backup),
+            (false, true) => (backup, primary),
+            (false, false) => return Err(GPTError::new(ErrorType::ChecksumError))
+        };
+        if good.part_count != bad.part_count || good.part_size != bad.part_size || good.part_crc != bad.part_crc {
+            return Err(GPTError::new(ErrorType::PartitionArrayChecksumError));
+        }
 
-        write_uuid(&mut cur, self.gpt_uuid)?;
+        let buf = good.read_partitions(read, options)?;
+        write.seek(SeekFrom::Start(bad.part_start.to_bytes(options.block_size)))?;
+        write.write_all(&buf)?;
+        Ok(())
+    }
 
-        let part_start = if primary {
-            Block(2)
-        } else {
-            self.backup_gpt - GPTTable::ptable_len(self.partitions.len() as u64, options)
+    /// Export the table to a protective or hybrid MBR
+    pub fn to_mbr(&self, mode: MbrExportMode, _options: &GPTOptions) -> Result<MBR, GPTError> {
+        let ids = match mode {
+            MbrExportMode::Protective => return Ok(MBR::protective(self.backup_gpt.0.saturating_add(1))),
+            MbrExportMode::Hybrid(ids) => ids
         };
+        if ids.len() > 3 {
+            return Err(GPTError::new(ErrorType::TableFull));
+        }
+
+        let mut mbr = MBR::new();
+        let protective = MBRPartitionEntry {
+            bootable: false,
+            system_id: 0xEE,
+            start_lba: 1,
+            sector_count: cmp::min(self.first_usable.0.saturating_sub(1), 0xFFFFFFFF) as u32,
+            start_chs: [0u8; 3],
+            end_chs: [0u8; 3]
+        };
+        mbr.add_partition(protective).map_err(|_| GPTError::new(ErrorType::TableFull))?;
+
+        for id in ids {
+            let part = self.used_partition(id)?;
+            if part.end.0 > 0xFFFFFFFF {
+                return Err(GPTError::new(ErrorType::ExceedsMbrLimit));
+            }
+            let entry = MBRPartitionEntry {
+                bootable: part.is_legacy_bootable(),
+                system_id: types::mbr_id_for_type(&part.part_type),
+                start_lba: part.start.0 as u32,
+                sector_count: part.block_count() as u32,
+                start_chs: [0u8; 3],
+                end_chs: [0u8; 3]
+            };
+            mbr.add_partition(entry).map_err(|_| GPTError::new(ErrorType::TableFull))?;
+        }
+
+        Ok(mbr)
+    }
+
+    /// Write a protective MBR covering the whole disk to block 0
+    ///
+    /// Only the partition table and the boot signature are replaced, so the boot code and the
+    /// disk signature already in block 0 are kept.
+    pub fn write_protective_mbr<W: Write + Seek>(&self, write: &mut W) -> Result<(), GPTError> {
+        MBR::protective(self.backup_gpt.0.saturating_add(1)).write_partition_table(write, MbrWriteMode::LbaOnly)?;
+        Ok(())
+    }
+
+    fn write_gpt<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions, primary: bool) -> Result<(), GPTError> {
+
+        let (mypos, part_start) = self.copy_location(options, primary);
+
+        // Now we actually write the table to disk
+        write.seek(SeekFrom::Start(part_start.to_bytes(options.block_size)))?;
+        let part_crc = self.write_partitions(write, options)?;
+        let header = self.serialize_header(options, primary, part_crc)?;
+
+        // Fully zero the sector for the actual GPT
+        let buf = vec![0u8; options.block_size as usize];
+        write.seek(SeekFrom::Start(mypos.to_bytes(options.block_size)))?;
+        write.write_all(&buf)?;
+
+        // Write the actual GPT
+        write.seek(SeekFrom::Start(mypos.to_bytes(options.block_size)))?;
+        write.write_all(&header)?;
+
+        Ok(())
+
+    }
+
+    /// Gets the location of the header and the partition table of the primary or backup GPT
+    fn copy_location(&self, options: &GPTOptions, primary: bool) -> (Block, Block) {
+        if primary {
+            (self.primary_gpt, self.part_start)
+        } else {
+            (self.backup_gpt, self.backup_gpt - GPTTable::ptable_len(self.partition_count as u64, options))
+        }
+    }
+
+    /// Gets the byte ranges `write` modifies as (offset, length) pairs
+    ///
+    /// These are the protective MBR if enabled in the options, followed by the header sector and
+    /// the partition table of the primary and of the backup GPT.
+    pub fn write_plan(&self, options: &GPTOptions) -> Vec<(u64, u64)> {
+        let mut plan = Vec::new();
+        if options.protective_mbr {
+            plan.push((0, 512));
+        }
+        for &primary in &[true, false] {
+            let (mypos, part_start) = self.copy_location(options, primary);
+            plan.push((mypos.to_bytes(options.block_size), options.block_size as u64));
+            plan.push((part_start.to_bytes(options.block_size), self.partition_count as u64 * 128));
+        }
+        plan
+    }
+
+    /// Rewrite only the partition tables of both GPT copies and patch the checksums in both
+    /// headers
+    ///
+    /// The remaining header fields are not written, so they have to match this table already,
+    /// e.g. when only partitions were changed since the table was written.
+    pub fn update_partition_arrays<W: Write + Seek>(&self, write: &mut W, options: &GPTOptions) -> Result<(), GPTError> {
+        for &primary in &[true, false] {
+            let (mypos, part_start) = self.copy_location(options, primary);
+
+            write.seek(SeekFrom::Start(part_start.to_bytes(options.block_size)))?;
+            let part_crc = self.write_partitions(write, options)?;
+            let header = self.serialize_header(options, primary, part_crc)?;
+
+            let header_pos = mypos.to_bytes(options.block_size);
+            write.seek(SeekFrom::Start(header_pos + 16))?;
+            write.write_all(&header[16..20])?;
+            write.seek(SeekFrom::Start(header_pos + 88))?;
+            write.write_all(&header[88..92])?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize the header of the primary or backup GPT, including its checksum
+    fn serialize_header(&self, options: &GPTOptions, primary: bool, part_crc: u32) -> Result<Vec<u8>, GPTError> {
+
+        // Start from the loaded header, so reserved bytes are preserved
+        let mut gpt = self.raw_header.clone();
+        gpt.resize(self.header_size() as usize, 0u8);
+
+        let mut cur = Cursor::new(gpt);
+
+        // Magic Bytes
+        cur.write_all(&GPT_MAGIC)?;
+        // Revision
+        cur.write_all(&[0x00, 0x00, 0x01, 0x00])?;
+        // Header size
+        cur.write_u32::<LittleEndian>(self.header_size())?;
+        // CRC32 sum - for now 0
+        cur.write_u32::<LittleEndian>(0)?;
+        // Reserved, keep whatever was loaded
+        cur.seek(SeekFrom::Current(4))?;
+
+        if primary {
+            cur.write_u64::<LittleEndian>(self.primary_gpt.0)?;
+            cur.write_u64::<LittleEndian>(self.backup_gpt.0)?;
+        } else {
+            cur.write_u64::<LittleEndian>(self.backup_gpt.0)?;
+            cur.write_u64::<LittleEndian>(self.primary_gpt.0)?;
+        }
+
+        cur.write_u64::<LittleEndian>(self.first_usable.0)?;
+        cur.write_u64::<LittleEndian>(self.last_usable.0)?;
+
+        write_uuid(&mut cur, self.gpt_uuid)?;
 
+        let (_, part_start) = self.copy_location(options, primary);
         cur.write_u64::<LittleEndian>(part_start.0)?;
 
-        cur.write_u32::<LittleEndian>(self.partitions.len() as u32)?;
+        cur.write_u32::<LittleEndian>(self.partition_count)?;
 
         cur.write_u32::<LittleEndian>(128)?;
 
+        // Write CRC of partition table
+        cur.write_u32::<LittleEndian>(part_crc)?;
+
+        cur.seek(SeekFrom::Start(16))?;
 
-        // Write part table
-        let mut part_tab = Vec::new();
-        part_tab.resize(self.partitions.len() * 128, 0u8);
+        let hdr_crc = options.checksum(cur.get_ref());
+        cur.write_u32::<LittleEndian>(hdr_crc)?;
 
-        let mut pcur = Cursor::new(part_tab);
+        Ok(cur.into_inner())
+    }
 
+    /// Write the partition table as it is stored on disk at the current position and return its
+    /// checksum
+    ///
+    /// The table is serialized in chunks of `ENTRIES_PER_CHUNK` entries, so memory use does not
+    /// grow with the number of slots.
+    fn write_partitions(&self, write: &mut dyn Write, options: &GPTOptions) -> Result<u32, GPTError> {
         let empty = PartitionEntry::empty();
+        let mut chunk = Cursor::new(vec![0u8; ENTRIES_PER_CHUNK * 128]);
+        let mut crc = (options.crc32)();
+
+        let count = self.partition_count as usize;
+        for chunk_start in (0..count).step_by(ENTRIES_PER_CHUNK) {
+            chunk.set_position(0);
+            for id in chunk_start..cmp::min(chunk_start + ENTRIES_PER_CHUNK, count) {
+                let p = match self.partitions.get(id) {
+                    Some(Some(p)) => p,
+                    _ => &empty
+                };
+                serialize_entry(&mut chunk, p)?;
+            }
 
-        for p in &self.partitions {
-            let p = match p {
-                &Some(ref p) => p,
-                &None => &empty
-            };
+            let len = chunk.position() as usize;
+            let buf = &chunk.get_ref()[..len];
+            write.write_all(buf)?;
+            crc.update(buf);
+        }
+
+        Ok(crc.sum())
+    }
 
-            write_uuid(&mut pcur, p.part_type)?;
-            write_uuid(&mut pcur, p.part_id)?;
-            pcur.write_u64::<LittleEndian>(p.start.0)?;
-            pcur.write_u64::<LittleEndian>(p.end.0)?;
-            pcur.write_u64::<LittleEndian>(p.flags)?;
-            write_utf16_le(&mut pcur, &p.name)?;
+    /// Serialize a single partition entry exactly as it would be written to disk
+    ///
+    /// Unused slots are returned as all zeroes. Useful for hex dumping an entry when debugging.
+    pub fn partition_entry_bytes(&self, id: u64, _options: &GPTOptions) -> Result<[u8; 128], GPTError> {
+        if id as usize >= self.partitions.len() {
+            return Err(GPTError::new(ErrorType::InvalidID));
+        }
+        let mut ret = [0u8; 128];
+        if let Some(ref p) = self.partitions[id as usize] {
+            serialize_entry(&mut Cursor::new(&mut ret[..]), p)?;
         }
+        Ok(ret)
+    }
 
-        let part_crc = CRC32::new().checksum(pcur.get_ref());
+    /// Calculate the checksum of the primary GPT header as it would be written
+    pub fn header_crc(&self, options: &GPTOptions) -> u32 {
+        self.header_crc_of(options, true)
+    }
 
-        // Write CRC of partition table
-        cur.write_u32::<LittleEndian>(part_crc)?;
+    fn header_crc_of(&self, options: &GPTOptions, primary: bool) -> u32 {
+        let header = self.serialize_header(options, primary, self.partition_array_crc(options))
+            .expect("Serializing to memory can not fail");
+        LittleEndian::read_u32(&header[16..20])
+    }
 
-        // Now we actually write the table to disk
-        write.seek(SeekFrom::Start(part_start.to_bytes(options.block_size)))?;
-        write.write(pcur.get_ref())?;
+    /// Calculate the checksum of the partition table as it would be written
+    pub fn partition_array_crc(&self, options: &GPTOptions) -> u32 {
+        self.write_partitions(&mut sink(), options).expect("Serializing to memory can not fail")
+    }
 
-        cur.seek(SeekFrom::Start(16))?;
+    fn ptable_len(pcount: u64, options: &GPTOptions) -> Block {
+        let (blocks, rest) = Block::from_bytes_offset(pcount * 128, options.block_size);
+        if rest != 0 {
+            blocks + Block(1)
+        } else {
+            blocks
+        }
+    }
+
+    /// Gets the size of the GPT header in bytes
+    ///
+    /// This is 92 for new tables. Tables loaded from disk keep the size of their header, and
+    /// any reserved bytes in it are written back unchanged.
+    pub fn header_size(&self) -> u32 {
+        cmp::max(self.raw_header.len(), 92) as u32
+    }
+
+    /// Gets the number of slots in the partition table, including empty ones
+    pub fn partition_count(&self) -> u32 {
+        self.partition_count
+    }
 
-        let hdr_crc = {
-            let buf = cur.get_ref();
-            CRC32::new().checksum(&buf)
+    /// Change the number of slots in the partition table
+    ///
+    /// The count must be a multiple of 4, at most 65536 and large enough to hold all used slots.
+    /// The usable range is adjusted to the new size of the partition table, which fails if an
+    /// existing partition would end up outside of it.
+    pub fn set_partition_count(&mut self, count: u32, options: &GPTOptions) -> Result<(), GPTError> {
+        let used = self.partitions.iter().rposition(|p| p.is_some()).map_or(0, |i| i + 1);
+        if !count.is_multiple_of(4) || (count as usize) < used || count > MAX_PART_COUNT {
+            return Err(GPTError::new(ErrorType::InvalidPartitionCount(count)));
+        }
+
+        let ptable_len = GPTTable::ptable_len(count as u64, options);
+        let first_usable = self.part_start + ptable_len;
+        let last_usable = match self.backup_gpt.0.checked_sub(ptable_len.0 + 1) {
+            Some(block) => Block(block),
+            None => return Err(GPTError::new(ErrorType::InvalidPartitionCount(count)))
         };
-        cur.write_u32::<LittleEndian>(hdr_crc)?;
+        let fits = self.partitions.iter()
+            .filter_map(|p| p.as_ref())
+            .all(|p| p.start >= first_usable && p.end <= last_usable);
+        if !fits || first_usable > last_usable {
+            return Err(GPTError::new(ErrorType::InvalidPartitionCount(count)));
+        }
 
-        // Fully zero the sector for the actual GPT
-        let mut buf = Vec::new();
-        buf.resize(options.block_size as usize, 0u8);
-        write.seek(SeekFrom::Start(mypos.to_bytes(options.block_size)))?;
-        write.write(&buf)?;
+        self.partitions.resize(count as usize, None);
+        self.partition_count = count;
+        self.first_usable = first_usable;
+        self.last_usable = last_usable;
+        Ok(())
+    }
 
-        // Write the actual GPT
-        write.seek(SeekFrom::Start(mypos.to_bytes(options.block_size)))?;
-        write.write(cur.get_ref())?;
+    /// Reduce the number of slots in the partition table to the smallest multiple of 4 that
+    /// still holds the last used slot, and grow the usable range accordingly
+    ///
+    /// Returns the new number of slots. The table is left unchanged if the usable range can not
+    /// be adjusted, e.g. because of an invalid layout.
+    pub fn shrink_partition_array(&mut self, options: &GPTOptions) -> u32 {
+        let used = self.partitions.iter().rposition(|p| p.is_some()).map_or(0, |i| i + 1) as u32;
+        let count = used.div_ceil(4) * 4;
+        if count < self.partition_count {
+            // Can only fail if the table was invalid to begin with
+            let _ = self.set_partition_count(count, options);
+        }
+        self.partition_count
+    }
 
+    /// Move the backup GPT to the last block of a disk of `disk_blocks` blocks
+    ///
+    /// The end of the usable range is moved along with it, partitions are not changed. Fails if
+    /// the disk is too small for the GPT or if a partition would end up outside the usable range.
+    pub fn relocate_backup(&mut self, disk_blocks: u64, options: &GPTOptions) -> Result<(), GPTError> {
+        let ptable_len = GPTTable::ptable_len(self.partition_count as u64, options);
+        let backup_gpt = match disk_blocks.checked_sub(1) {
+            Some(block) => Block(block),
+            None => return Err(GPTError::new(ErrorType::DiskTooSmall))
+        };
+        let last_usable = match backup_gpt.0.checked_sub(ptable_len.0 + 1) {
+            Some(block) if block >= self.first_usable.0 => Block(block),
+            _ => return Err(GPTError::new(ErrorType::DiskTooSmall))
+        };
+        if self.partitions.iter().filter_map(|p| p.as_ref()).any(|p| p.end > last_usable) {
+            return Err(GPTError::new(ErrorType::OutOfBounds));
+        }
+
+        self.backup_gpt = backup_gpt;
+        self.last_usable = last_usable;
         Ok(())
+    }
+
+    /// Adjust the GPT to a disk of `disk_blocks` blocks
+    ///
+    /// The usable range is set to everything between the primary and the backup partition
+    /// table, with the backup GPT at the last block. Fails if a partition would end up outside
+    /// the usable range.
+    pub fn set_disk_size(&mut self, disk_blocks: u64, options: &GPTOptions) -> Result<(), GPTError> {
+        let first_usable = self.part_start + GPTTable::ptable_len(self.partition_count as u64, options);
+        if self.partitions.iter().filter_map(|p| p.as_ref()).any(|p| p.start < first_usable) {
+            return Err(GPTError::new(ErrorType::OutOfBounds));
+        }
 
+        let old_first_usable = self.first_usable;
+        self.first_usable = first_usable;
+        let res = self.relocate_backup(disk_blocks, options);
+        if res.is_err() {
+            self.first_usable = old_first_usable;
+        }
+        res
     }
 
-    fn ptable_len(pcount: u64, options: &GPTOptions) -> Block {
-        Block::from_bytes(pcount * 128, options.block_size).expect("Partition count must be devidable by 4")
+    /// Gets the first and the last block usable for partitions
+    pub fn usable_range(&self) -> (Block, Block) {
+        (self.first_usable, self.last_usable)
+    }
+
+    /// Gets the header checksum as it was stored on disk when the table was loaded
+    pub fn stored_header_crc(&self) -> u32 {
+        self.checksum
+    }
+
+    /// Checks whether the stored checksums still match the table, i.e. whether the table is
+    /// unchanged since it was loaded
+    pub fn is_consistent(&self, options: &GPTOptions) -> bool {
+        self.header_crc_of(options, !self.loaded_from_backup) == self.checksum
+            && self.partition_array_crc(options) == self.part_checksum
+    }
+
+    /// Returns true if the primary GPT was corrupt and the table was loaded from the backup GPT
+    pub fn loaded_from_backup(&self) -> bool {
+        self.loaded_from_backup
+    }
+
+    /// Gets the location of the primary GPT header
+    pub fn primary_header_lba(&self) -> Block {
+        self.primary_gpt
+    }
+
+    /// Gets the location of the backup GPT header
+    pub fn backup_header_lba(&self) -> Block {
+        self.backup_gpt
+    }
+
+    /// Checks whether the primary header is at block 1 and the backup header at the last block
+    /// of a disk of `disk_blocks` blocks
+    pub fn headers_point_correctly(&self, disk_blocks: u64) -> bool {
+        self.primary_gpt == Block(1) && disk_blocks.checked_sub(1) == Some(self.backup_gpt.0)
+    }
+
+    /// Gets the GUID of the disk
+    pub fn disk_guid(&self) -> UUID {
+        self.gpt_uuid
+    }
+
+    /// Sets the GUID of the disk
+    pub fn set_disk_guid(&mut self, uuid: UUID) {
+        self.gpt_uuid = uuid;
+    }
+
+    /// Assigns a new random GUID to the disk, e.g. after cloning it
+    pub fn regenerate_disk_guid(&mut self) {
+        self.gpt_uuid = UUID::new_v4();
+    }
+
+    /// Assigns a new random PARTUUID to every partition, e.g. after cloning the disk. The
+    /// partition types are kept.
+    pub fn regenerate_partition_guids(&mut self) {
+        for part in self.partitions.iter_mut().filter_map(|p| p.as_mut()) {
+            part.part_id = UUID::new_v4();
+        }
     }
 
     /// Gets the amount of partitions that are in use.
@@ -446,6 +1611,73 @@ impl GPTTable {
         &self.partitions
     }
 
+    /// Iterate over all used partition slots, together with their slot id
+    pub fn used_partitions(&self) -> impl Iterator<Item = (usize, &PartitionEntry)> {
+        self.partitions.iter().enumerate().filter_map(|(i, p)| p.as_ref().map(|p| (i, p)))
+    }
+
+    /// Get all used partitions ordered by their first block, together with their slot id
+    pub fn partitions_by_start(&self) -> Vec<(usize, &PartitionEntry)> {
+        let mut parts = self.used_partitions().collect::<Vec<_>>();
+        parts.sort_by_key(|&(_, p)| p.start);
+        parts
+    }
+
+    /// Compare the partition slots of this table to those of `other`
+    ///
+    /// Changes are reported from this table to `other`, ordered by slot id.
+    pub fn diff(&self, other: &GPTTable) -> Vec<PartitionChange> {
+        let len = cmp::max(self.partitions.len(), other.partitions.len());
+        let mut ret = Vec::new();
+        for id in 0..len {
+            let old = self.partitions.get(id).and_then(|p| p.as_ref());
+            let new = other.partitions.get(id).and_then(|p| p.as_ref());
+            match (old, new) {
+                (None, Some(new)) => ret.push(PartitionChange::Added(id, new.clone())),
+                (Some(old), None) => ret.push(PartitionChange::Removed(id, old.clone())),
+                (Some(old), Some(new)) if old != new => {
+                    ret.push(PartitionChange::Modified(id, old.clone(), new.clone()))
+                },
+                _ => {}
+            }
+        }
+        ret
+    }
+
+    /// Compare the disk GUID and usable range of this table to those of `other`
+    pub fn header_diff(&self, other: &GPTTable) -> HeaderDiff {
+        let old_range = (self.first_usable, self.last_usable);
+        let new_range = (other.first_usable, other.last_usable);
+        HeaderDiff {
+            disk_guid: if self.gpt_uuid != other.gpt_uuid {
+                Some((self.gpt_uuid, other.gpt_uuid))
+            } else {
+                None
+            },
+            usable_range: if old_range != new_range {
+                Some((old_range, new_range))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Find the partition with the given PARTUUID
+    pub fn find_by_partuuid(&self, uuid: &UUID) -> Option<(usize, &PartitionEntry)> {
+        self.used_partitions().find(|&(_, p)| p.part_id == *uuid)
+    }
+
+    /// Find the first partition with the given name. The name has to match exactly, including
+    /// case.
+    pub fn find_by_name(&self, name: &str) -> Option<(usize, &PartitionEntry)> {
+        self.used_partitions().find(|&(_, p)| p.name == name)
+    }
+
+    /// Find all partitions of the given type, ordered by slot id
+    pub fn find_by_type(&self, type_uuid: &UUID) -> Vec<(usize, &PartitionEntry)> {
+        self.used_partitions().filter(|&(_, p)| p.part_type == *type_uuid).collect()
+    }
+
     /// Get the first free partition ID
     ///
     /// Returns Some(id) if there is still space  
@@ -461,16 +1693,228 @@ impl GPTTable {
 
     /// Set a partition entry to whatever you specified
     pub fn set_partition(&mut self, id: u64, part: PartitionEntry) -> Result<(), GPTError> {
-        if id as usize > self.partitions.len() - 1 {
+        if id as usize >= self.partitions.len() {
             return Err(GPTError::new(ErrorType::InvalidID));
         }
         self.partitions[id as usize] = Some(part);
         Ok(())
     }
 
+    /// Set a partition entry, but only if it lies within the usable area and does not overlap
+    /// any other partition. The partition currently in slot `id` is ignored for the check.
+    pub fn set_partition_checked(&mut self, id: u64, part: PartitionEntry) -> Result<(), GPTError> {
+        if id as usize >= self.partitions.len() {
+            return Err(GPTError::new(ErrorType::InvalidID));
+        }
+        if part.start > part.end || part.start < self.first_usable || part.end > self.last_usable {
+            return Err(GPTError::new(ErrorType::OutOfBounds));
+        }
+        let overlap = self.partitions.iter().enumerate()
+            .filter(|&(i, _)| i != id as usize)
+            .filter_map(|(_, p)| p.as_ref())
+            .any(|p| p.overlaps(&part));
+        if overlap {
+            return Err(GPTError::new(ErrorType::Overlap));
+        }
+        self.set_partition(id, part)
+    }
+
+    /// Change the last block of a partition, keeping its start
+    ///
+    /// Fails if the partition would leave the usable area or overlap another partition.
+    pub fn resize_partition(&mut self, id: u64, new_end: Block) -> Result<(), GPTError> {
+        let mut part = self.used_partition(id)?.clone();
+        part.end = new_end;
+        self.set_partition_checked(id, part)
+    }
+
+    /// Move a partition to start at `new_start`, keeping its size
+    ///
+    /// Only the partition table is changed, the data of the partition is not moved. Fails if
+    /// the partition would leave the usable area or overlap another partition.
+    pub fn move_partition(&mut self, id: u64, new_start: Block) -> Result<(), GPTError> {
+        let mut part = self.used_partition(id)?.clone();
+        part.end = match part.end.checked_sub(part.start).and_then(|len| new_start.checked_add(len)) {
+            Some(end) => end,
+            None => return Err(GPTError::new(ErrorType::OutOfBounds))
+        };
+        part.start = new_start;
+        self.set_partition_checked(id, part)
+    }
+
+    fn used_partition(&self, id: u64) -> Result<&PartitionEntry, GPTError> {
+        match self.partitions.get(id as usize) {
+            Some(Some(part)) => Ok(part),
+            _ => Err(GPTError::new(ErrorType::InvalidID))
+        }
+    }
+
+    /// Get a window on the data of a partition
+    pub fn partition_window<'a, T: Read + Seek>(&self, id: u64, backend: &'a mut T, options: &GPTOptions) -> Result<Window<'a, T>, GPTError> {
+        let part = self.used_partition(id)?;
+        let start = part.start.to_bytes(options.block_size);
+        Ok(Window::new(backend, start, part.size_bytes(options.block_size)))
+    }
+
+    /// Find all pairs of partitions whose block ranges intersect
+    ///
+    /// Returns pairs of slot ids, with the lower id first.
+    pub fn find_overlaps(&self) -> Vec<(usize, usize)> {
+        let mut ret = Vec::new();
+        for (i, a) in self.partitions.iter().enumerate() {
+            for (j, b) in self.partitions.iter().enumerate().skip(i + 1) {
+                if let (Some(a), Some(b)) = (a.as_ref(), b.as_ref()) {
+                    if a.overlaps(b) {
+                        ret.push((i, j));
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Find all partitions which do not lie entirely within the usable area of the disk
+    pub fn find_out_of_bounds(&self) -> Vec<usize> {
+        self.used_partitions()
+            .filter(|&(_, p)| p.start > p.end || p.start < self.first_usable || p.end > self.last_usable)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Find all unused regions within the usable area of the disk
+    ///
+    /// Returns inclusive block ranges, ordered by their start.
+    pub fn free_regions(&self) -> Vec<(Block, Block)> {
+        self.free_regions_iter().collect()
+    }
+
+    /// Iterate over all unused regions within the usable area of the disk
+    ///
+    /// Yields the same inclusive block ranges as `free_regions`, but computes them one at a
+    /// time instead of collecting them.
+    pub fn free_regions_iter(&self) -> impl Iterator<Item = (Block, Block)> {
+        let mut used = self.partitions.iter()
+            .filter_map(|p| p.as_ref())
+            .map(|p| (p.start, p.end))
+            .collect::<Vec<_>>();
+        used.sort();
+
+        FreeRegions {
+            used: used.into_iter(),
+            next_free: self.first_usable,
+            last_usable: self.last_usable,
+            done: false
+        }
+    }
+
+    /// Find the largest unused region within the usable area of the disk
+    pub fn largest_free_region(&self) -> Option<(Block, Block)> {
+        // max_by_key returns the last maximum, so reverse to prefer the region closest to the start
+        self.free_regions().into_iter().rev().max_by_key(|&(start, end)| end - start)
+    }
+
+    /// Summarize the space usage of the disk
+    ///
+    /// Overlapping partitions and partitions outside of the usable range are only counted once
+    /// respectively not at all, so used and free blocks always add up to the usable blocks.
+    pub fn usage(&self, _options: &GPTOptions) -> DiskUsage {
+        let regions = self.free_regions();
+        let total_usable_blocks = if self.last_usable >= self.first_usable {
+            (self.last_usable - self.first_usable).0.saturating_add(1)
+        } else {
+            0
+        };
+        let free_blocks = regions.iter().map(|&(start, end)| (end - start).0.saturating_add(1)).fold(0, u64::saturating_add);
+
+        DiskUsage {
+            total_usable_blocks,
+            used_blocks: total_usable_blocks - free_blocks,
+            free_blocks,
+            largest_free_blocks: regions.iter().map(|&(start, end)| (end - start).0.saturating_add(1)).max().unwrap_or(0),
+            fragment_count: regions.len()
+        }
+    }
+
+    /// Allocate a new partition of `size` blocks in the first free region that can hold it
+    ///
+    /// The start of the partition is rounded up to a multiple of `alignment`. The new partition
+    /// gets a random PARTUUID and is placed in the first free slot, whose id is returned.
+    pub fn allocate(&mut self, size: Block, alignment: Block, part_type: UUID) -> Result<u64, GPTError> {
+        if size == Block(0) {
+            return Err(GPTError::new(ErrorType::OutOfBounds));
+        }
+        let id = match self.next_id() {
+            Some(id) => id,
+            None => return Err(GPTError::new(ErrorType::TableFull))
+        };
+        for (free_start, free_end) in self.free_regions() {
+            let start = free_start.align_up(alignment);
+            let end = match start.checked_add(size) {
+                Some(end) => end - Block(1),
+                None => continue
+            };
+            if end <= free_end {
+                let mut part = PartitionEntry::empty();
+                part.part_type = part_type;
+                part.part_id = UUID::new_v4();
+                part.start = start;
+                part.end = end;
+                self.set_partition(id, part)?;
+                return Ok(id);
+            }
+        }
+
+        Err(GPTError::new(ErrorType::NoSpace))
+    }
+
+    /// Swap the contents of two partition slots. Either slot may be empty.
+    pub fn swap_partitions(&mut self, a: u64, b: u64) -> Result<(), GPTError> {
+        if a as usize >= self.partitions.len() || b as usize >= self.partitions.len() {
+            return Err(GPTError::new(ErrorType::InvalidID));
+        }
+        self.partitions.swap(a as usize, b as usize);
+        Ok(())
+    }
+
+    /// Move all partitions to the lowest slots, keeping their order and block ranges
+    ///
+    /// Returns the old and new slot id of every partition that was moved
+    pub fn compact(&mut self) -> Vec<(u64, u64)> {
+        let mut moved = Vec::new();
+        let mut next = 0;
+        for id in 0..self.partitions.len() {
+            if self.partitions[id].is_some() {
+                if id != next {
+                    self.partitions.swap(id, next);
+                    moved.push((id as u64, next as u64));
+                }
+                next += 1;
+            }
+        }
+        moved
+    }
+
+    /// Gets the attribute flags of a partition. Returns None if the slot is empty.
+    pub fn partition_flags(&self, id: u64) -> Option<u64> {
+        self.used_partition(id).ok().map(|p| p.flags)
+    }
+
+    /// Replace the attribute flags of a partition, e.g. with `FLAG_LEGACY_BOOTABLE` added
+    ///
+    /// Fails with `InvalidID` if the slot is empty.
+    pub fn set_partition_flags(&mut self, id: u64, flags: u64) -> Result<(), GPTError> {
+        match self.partitions.get_mut(id as usize) {
+            Some(&mut Some(ref mut part)) => {
+                part.flags = flags;
+                Ok(())
+            },
+            _ => Err(GPTError::new(ErrorType::InvalidID))
+        }
+    }
+
     /// Mark a partition slot as empty
     pub fn delete_partition(&mut self, id: u64) -> Result<(), GPTError> {
-        if id as usize > self.partitions.len() - 1 {
+        if id as usize >= self.partitions.len() {
             return Err(GPTError::new(ErrorType::InvalidID));
         }
         self.partitions[id as usize] = None;
@@ -478,7 +1922,92 @@ impl GPTTable {
     }
 }
 
-fn write_utf16_le(write: &mut Write, s: &str) -> Result<(), GPTError> {
+/// Copy the data of partition `id` from `src` to the same location on `dst`
+///
+/// The data is copied in chunks of 64 KiB. Returns the number of bytes copied.
+pub fn copy_partition_data<R: Read + Seek, W: Write + Seek>(table: &GPTTable, id: u64, src: &mut R, dst: &mut W, options: &GPTOptions) -> Result<u64, GPTError> {
+    let mut window = table.partition_window(id, src, options)?;
+    dst.seek(SeekFrom::Start(window.start()))?;
+
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut copied = 0;
+    while copied < window.len() {
+        let len = window.read(&mut buf)?;
+        if len == 0 {
+            return Err(GPTError::new(ErrorType::IOError(IOError::new(ErrorKind::UnexpectedEof, "Partition exceeds the source disk"))));
+        }
+        dst.write_all(&buf[..len])?;
+        copied += len as u64;
+    }
+
+    Ok(copied)
+}
+
+/// Iterator over the gaps between partitions, see `GPTTable::free_regions_iter`
+struct FreeRegions {
+    /// Block ranges of all partitions, sorted by their start
+    used: ::std::vec::IntoIter<(Block, Block)>,
+    /// First block not covered by any partition seen so far
+    next_free: Block,
+    last_usable: Block,
+    done: bool
+}
+
+impl Iterator for FreeRegions {
+    type Item = (Block, Block);
+
+    fn next(&mut self) -> Option<(Block, Block)> {
+        if self.done {
+            return None;
+        }
+        for (start, end) in &mut self.used {
+            let gap = if start > self.next_free {
+                let gap_end = cmp::min(start - Block(1), self.last_usable);
+                if self.next_free <= gap_end {
+                    Some((self.next_free, gap_end))
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+            match end.checked_add(Block(1)) {
+                Some(after) => self.next_free = cmp::max(self.next_free, after),
+                None => {
+                    // The partition reaches the end of the address space, nothing can follow
+                    self.done = true;
+                    return gap;
+                }
+            }
+            if gap.is_some() {
+                return gap;
+            }
+        }
+
+        // The space after the last partition
+        self.done = true;
+        if self.next_free <= self.last_usable {
+            Some((self.next_free, self.last_usable))
+        } else {
+            None
+        }
+    }
+}
+
+/// Write a single 128 byte partition entry
+fn serialize_entry(write: &mut dyn Write, p: &PartitionEntry) -> Result<(), GPTError> {
+    write_uuid(write, p.part_type)?;
+    write_uuid(write, p.part_id)?;
+    write.write_u64::<LittleEndian>(p.start.0)?;
+    write.write_u64::<LittleEndian>(p.end.0)?;
+    write.write_u64::<LittleEndian>(p.flags)?;
+    write_utf16_le(write, &p.name)?;
+    Ok(())
+}
+
+/// Write a partition name as 36 UTF-16 code units, each stored little endian with the low
+/// byte first. GPT is always little endian, regardless of the platform.
+fn write_utf16_le(write: &mut dyn Write, s: &str) -> Result<(), GPTError> {
     let buf = s.encode_utf16().take(36).collect::<Vec<_>>();
     let mut buf2 = [0u16; 36];
     cp(&buf, &mut buf2);
@@ -486,43 +2015,55 @@ fn write_utf16_le(write: &mut Write, s: &str) -> Result<(), GPTError> {
     Ok(())
 }
 
-fn write_u16_buf<T: ByteOrder>(write: &mut Write, buf: &[u16]) -> IOResult<()> {
+fn write_u16_buf<T: ByteOrder>(write: &mut dyn Write, buf: &[u16]) -> IOResult<()> {
     for i in 0..buf.len() {
         write.write_u16::<T>(buf[i])?;
     }
     Ok(())
 }
 
-fn read_utf16_le(read: &mut Read, ignore_err: bool) -> Result<String, GPTError> {
+/// Read a partition name stored as 36 little endian UTF-16 code units
+fn read_utf16_le(read: &mut dyn Read, mode: Utf16Mode) -> Result<String, GPTError> {
     let mut buf = [0u16; 36];
     read_u16_buf::<LittleEndian>(read, &mut buf)?;
-    let ret = match String::from_utf16(&buf) {
-        Ok(x) => x,
-        Err(_) => if ignore_err {
-            String::new()
-        } else {
-            return Err(GPTError::new(ErrorType::UTF16Error))
-        }
-    };
-
-    Ok(match ret.find('\0') {
-        Some(x) => String::from(&ret[0..x]),
-        None => ret
-    })
+    // Whatever follows the terminating NUL is ignored
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    match mode {
+        Utf16Mode::Strict => String::from_utf16(&buf[..len]).map_err(|_| GPTError::new(ErrorType::UTF16Error)),
+        Utf16Mode::Lossy => Ok(String::from_utf16_lossy(&buf[..len])),
+        Utf16Mode::EmptyOnError => Ok(String::from_utf16(&buf[..len]).unwrap_or_default())
+    }
 }
 
-fn read_u16_buf<T: ByteOrder>(read: &mut Read, buf: &mut[u16]) -> IOResult<()> {
+fn read_u16_buf<T: ByteOrder>(read: &mut dyn Read, buf: &mut[u16]) -> IOResult<()> {
     for i in 0..buf.len() {
         buf[i] = read.read_u16::<T>()?;
     }
     Ok(())
 }
 
-fn read_uuid(read: &mut Read) -> Result<UUID, GPTError> {
+fn read_uuid(read: &mut dyn Read) -> Result<UUID, GPTError> {
     let mut buf = [0u8; 16];
+    read.read_exact(&mut buf)?;
+    uuid_from_disk_bytes(&buf)
+}
+
+fn write_uuid(write: &mut dyn Write, uuid: UUID) -> Result<(), GPTError> {
+    write.write_all(&uuid_to_disk_bytes(uuid))?;
+    Ok(())
+}
+
+/// The default CRC32 implementation used by `GPTOptions`
+pub fn crc32() -> Box<dyn Crc32> {
+    Box::new(CRC32::new())
+}
+
+/// Convert a UUID as it is stored in a GPT to a UUID
+///
+/// On disk the first three fields of a UUID are stored little endian, the last two big endian.
+pub fn uuid_from_disk_bytes(buf: &[u8; 16]) -> Result<UUID, GPTError> {
     let mut buf_endian_ffs = [0u8; 16];
-    read.read(&mut buf)?;
-    cp(&buf, &mut buf_endian_ffs);
+    cp(buf, &mut buf_endian_ffs);
     // Lets fix endianness
     swap_endian(&buf[0..4], &mut buf_endian_ffs[0..4]);
     swap_endian(&buf[4..6], &mut buf_endian_ffs[4..6]);
@@ -531,16 +2072,15 @@ fn read_uuid(read: &mut Read) -> Result<UUID, GPTError> {
     Ok(UUID::from_bytes(&buf_endian_ffs)?)
 }
 
-fn write_uuid(write: &mut Write, uuid: UUID) -> Result<(), GPTError> {
+/// Convert a UUID to the mixed endian form it is stored as in a GPT
+pub fn uuid_to_disk_bytes(uuid: UUID) -> [u8; 16] {
     let buf = uuid.as_bytes();
     let mut buf_out = [0u8; 16];
     cp(buf, &mut buf_out);
     swap_endian(&buf[0..4], &mut buf_out[0..4]);
     swap_endian(&buf[4..6], &mut buf_out[4..6]);
     swap_endian(&buf[6..8], &mut buf_out[6..8]);
-
-    write.write(&buf_out)?;
-    Ok(())
+    buf_out
 }
 
 fn swap_endian(input: &[u8], output: &mut [u8]) {
@@ -556,3 +2096,1396 @@ fn cp<T: Copy>(input: &[T], output: &mut [T]) {
         output[i] = input[i];
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::{ShortWriter, blank_image, gpt_image};
+
+    #[test]
+    fn create_write_load() {
+        let options = GPTOptions::default();
+        assert!(matches!(GPTTable::create(67, &options).unwrap_err().error_type(), ErrorType::DiskTooSmall));
+
+        let table = GPTTable::create(68, &options).unwrap();
+        assert_eq!(table.usable_range(), (Block(34), Block(34)));
+        assert_eq!(table.partitions().len(), 128);
+        assert_eq!(table.backup_header_lba(), Block(67));
+
+        let mut image = gpt_image(&table, 68, &options);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(loaded == table);
+        assert_eq!(loaded.disk_guid(), table.disk_guid());
+        assert_eq!(loaded.backup_header_lba(), Block(67));
+    }
+
+    #[test]
+    fn writes_protective_mbr() {
+        let options = GPTOptions { protective_mbr: true, ..GPTOptions::default() };
+        let table = GPTTable::create(1000, &options).unwrap();
+        let mut image = gpt_image(&table, 1000, &options);
+        assert_eq!(&image.get_ref()[510..512], &[0x55, 0xAA]);
+
+        let mbr = MBR::load(&mut image, false).unwrap();
+        assert!(mbr.is_protective());
+        let part = mbr.partitions()[0].unwrap();
+        assert_eq!((part.system_id, part.start_lba, part.sector_count), (0xEE, 1, 999));
+        assert!(GPTTable::load(&mut image, &options).unwrap() == table);
+
+        // Without the option block 0 is left alone
+        let image = gpt_image(&table, 1000, &GPTOptions::default());
+        assert!(image.get_ref()[..512].iter().all(|&b| b == 0));
+
+        // Existing boot code and disk signature are kept
+        let mut image = blank_image(1000, 512);
+        for b in &mut image.get_mut()[..446] {
+            *b = 0xAB;
+        }
+        table.write(&mut image, &options).unwrap();
+        assert!(image.get_ref()[..446].iter().all(|&b| b == 0xAB));
+        let mbr = MBR::load(&mut image, false).unwrap();
+        assert!(mbr.is_protective());
+        assert_eq!(mbr.disk_signature(), 0xABABABAB);
+    }
+
+    fn test_table(options: &GPTOptions) -> GPTTable {
+        let mut table = GPTTable::create(4096, options).unwrap();
+        // Not a multiple of ENTRIES_PER_CHUNK, so the last chunk is a partial one
+        table.set_partition_count(100, options).unwrap();
+        for id in &[0u64, 31, 32, 99] {
+            let start = Block(100 + id * 10);
+            let part = PartitionEntryBuilder::new()
+                .part_type(*types::LINUX_FILESYSTEM)
+                .range(start, start + Block(9))
+                .name("chunked")
+                .build()
+                .unwrap();
+            table.set_partition(*id, part).unwrap();
+        }
+        table
+    }
+
+    #[test]
+    fn falls_back_to_backup() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let image = gpt_image(&table, 4096, &options);
+        let backup_header = 4095 * 512;
+
+        let mut intact = image.clone();
+        assert!(!GPTTable::load(&mut intact, &options).unwrap().loaded_from_backup());
+
+        // Corrupt primary header
+        let mut corrupt = image.clone();
+        corrupt.get_mut()[512 + 16] ^= 0xff;
+        let loaded = GPTTable::load(&mut corrupt, &options).unwrap();
+        assert!(loaded.loaded_from_backup());
+        assert!(loaded == table);
+
+        // Corrupt primary partition table
+        let mut corrupt = image.clone();
+        corrupt.get_mut()[1024 + 5] ^= 0xff;
+        let loaded = GPTTable::load(&mut corrupt, &options).unwrap();
+        assert!(loaded.loaded_from_backup());
+        assert!(loaded == table);
+
+        // Both copies corrupt
+        corrupt.get_mut()[backup_header + 16] ^= 0xff;
+        let err = GPTTable::load(&mut corrupt, &options).unwrap_err();
+        assert!(err.is_checksum_error());
+
+        // Corrupt copies are only used with ignore_csum
+        let ignore = GPTOptions { ignore_csum: true, ..GPTOptions::default() };
+        assert!(!GPTTable::load(&mut corrupt, &ignore).unwrap().loaded_from_backup());
+    }
+
+    #[test]
+    fn specific_header_errors() {
+        let options = GPTOptions::default();
+        let image = gpt_image(&GPTTable::create(1000, &options).unwrap(), 1000, &options);
+        let load_patched = |offset: usize, value: u32| {
+            let mut image = image.clone();
+            LittleEndian::write_u32(&mut image.get_mut()[512 + offset..512 + offset + 4], value);
+            GPTTable::load(&mut image, &options).unwrap_err()
+        };
+
+        assert!(matches!(load_patched(8, 0x0002_0000).error_type(), ErrorType::InvalidVersion));
+        assert!(matches!(load_patched(12, 90).error_type(), ErrorType::UnsupportedHeaderSize(90)));
+        assert!(matches!(load_patched(12, 1024).error_type(), ErrorType::UnsupportedHeaderSize(1024)));
+        assert!(matches!(load_patched(72, 1).error_type(), ErrorType::InvalidPartitionTableStart(Block(1))));
+        assert!(matches!(load_patched(84, 64).error_type(), ErrorType::UnsupportedEntrySize(64)));
+    }
+
+    #[test]
+    fn partition_count_round_trip() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        assert!(matches!(table.set_partition_count(63, &options).unwrap_err().error_type(), ErrorType::InvalidPartitionCount(63)));
+        assert!(table.set_partition_count(MAX_PART_COUNT + 4, &options).is_err());
+        table.set_partition_count(64, &options).unwrap();
+        assert_eq!(table.usable_range().0, Block(18));
+
+        let mut image = gpt_image(&table, 1000, &options);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert_eq!(loaded.partition_count(), 64);
+        assert_eq!(loaded.partitions().len(), 64);
+
+        // Used slots can not be dropped
+        let mut table = test_table(&options);
+        assert!(table.set_partition_count(96, &options).is_err());
+        table.set_partition_count(128, &options).unwrap();
+    }
+
+    fn part(start: u64, end: u64) -> PartitionEntry {
+        PartitionEntryBuilder::new()
+            .part_type(*types::LINUX_FILESYSTEM)
+            .range(Block(start), Block(end))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn overlap_detection() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        table.set_partition_checked(0, part(34, 99)).unwrap();
+        table.set_partition_checked(1, part(100, 199)).unwrap();
+        assert!(matches!(table.set_partition_checked(2, part(150, 250)).unwrap_err().error_type(), ErrorType::Overlap));
+        assert!(matches!(table.set_partition_checked(2, part(10, 20)).unwrap_err().error_type(), ErrorType::OutOfBounds));
+        // Replacing a slot does not conflict with its old contents
+        table.set_partition_checked(1, part(100, 299)).unwrap();
+
+        table.set_partition(2, part(150, 250)).unwrap();
+        table.set_partition(3, part(0, 5)).unwrap();
+        assert_eq!(table.find_overlaps(), vec![(1, 2)]);
+        assert_eq!(table.find_out_of_bounds(), vec![3]);
+    }
+
+    #[test]
+    fn free_regions() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        assert_eq!(table.free_regions(), vec![(Block(34), Block(966))]);
+
+        table.set_partition(5, part(500, 599)).unwrap();
+        table.set_partition(1, part(34, 99)).unwrap();
+        table.set_partition(7, part(100, 199)).unwrap();
+        table.set_partition(9, part(900, 966)).unwrap();
+        assert_eq!(table.free_regions(), vec![(Block(200), Block(499)), (Block(600), Block(899))]);
+        assert_eq!(table.largest_free_region(), Some((Block(200), Block(499))));
+    }
+
+    #[test]
+    fn free_regions_partition_at_last_block() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(300, &options).unwrap();
+        table.set_partition(0, part(100, u64::MAX)).unwrap();
+        table.set_partition(1, part(200, 210)).unwrap();
+        assert_eq!(table.free_regions(), vec![(Block(34), Block(99))]);
+    }
+
+    #[test]
+    fn aligned_allocation() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(10000, &options).unwrap();
+        let a = table.allocate(Block(100), Block(2048), *types::LINUX_FILESYSTEM).unwrap();
+        let b = table.allocate(Block(100), Block(2048), *types::LINUX_SWAP).unwrap();
+        assert_eq!((a, b), (0, 1));
+
+        let first = table.partitions()[0].as_ref().unwrap();
+        assert_eq!((first.start, first.end), (Block(2048), Block(2147)));
+        assert_eq!(first.part_type, *types::LINUX_FILESYSTEM);
+        assert_eq!(table.partitions()[1].as_ref().unwrap().start, Block(4096));
+
+        let err = table.allocate(Block(4000), Block(2048), *types::LINUX_FILESYSTEM).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::NoSpace));
+        // Without alignment the gap before the first partition is used
+        let c = table.allocate(Block(2000), Block(1), *types::LINUX_FILESYSTEM).unwrap();
+        assert_eq!(table.partitions()[c as usize].as_ref().unwrap().start, Block(34));
+    }
+
+    #[test]
+    fn name_length() {
+        let mut p = part(100, 200);
+        p.set_name(&"a".repeat(36)).unwrap();
+        assert!(matches!(p.set_name(&"a".repeat(37)).unwrap_err().error_type(), ErrorType::NameTooLong));
+        assert_eq!(p.name, "a".repeat(36));
+        // Characters outside the BMP take two UTF-16 units
+        p.set_name(&"\u{1F600}".repeat(18)).unwrap();
+        assert!(p.set_name(&format!("{}a", "\u{1F600}".repeat(18))).is_err());
+    }
+
+    #[test]
+    fn attribute_flags() {
+        let mut p = part(100, 200);
+        p.set_required(true);
+        assert_eq!(p.flags, FLAG_REQUIRED);
+        p.set_no_block_io(true);
+        p.set_legacy_bootable(true);
+        assert_eq!(p.flags, 7);
+        assert!(p.is_required() && p.is_no_block_io() && p.is_legacy_bootable());
+        p.set_required(false);
+        assert_eq!(p.flags, FLAG_NO_BLOCK_IO | FLAG_LEGACY_BOOTABLE);
+
+        p.set_type_specific_bits(0x8001);
+        assert_eq!(p.flags, 0x8001_0000_0000_0006);
+        assert_eq!(p.type_specific_bits(), 0x8001);
+        p.set_type_specific_bits(0);
+        assert_eq!(p.flags, 6);
+    }
+
+    #[test]
+    fn checksums_without_writing() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let image = gpt_image(&table, 4096, &options);
+        let buf = image.get_ref();
+        assert_eq!(table.header_crc(&options), LittleEndian::read_u32(&buf[512 + 16..512 + 20]));
+        assert_eq!(table.partition_array_crc(&options), LittleEndian::read_u32(&buf[512 + 88..512 + 92]));
+        assert_eq!(table.partition_array_crc(&options), LittleEndian::read_u32(&buf[4095 * 512 + 88..4095 * 512 + 92]));
+    }
+
+    #[test]
+    fn stored_header_crc() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+
+        let mut loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(loaded.is_consistent(&options));
+        assert_eq!(loaded.stored_header_crc(), table.header_crc(&options));
+        loaded.delete_partition(0).unwrap();
+        assert!(!loaded.is_consistent(&options));
+
+        image.get_mut()[512 + 16] ^= 1;
+        let backup = GPTTable::load(&mut image, &options).unwrap();
+        assert!(backup.loaded_from_backup());
+        assert!(backup.is_consistent(&options));
+    }
+
+    #[test]
+    fn native_4k_sectors() {
+        let options = GPTOptions::new(4096).unwrap();
+        let mut table = GPTTable::new(1000 * 4096, 128, &options);
+        // 128 entries fit into 4 blocks
+        assert_eq!(table.usable_range(), (Block(6), Block(994)));
+        table.allocate(Block(10), Block(256), *types::LINUX_FILESYSTEM).unwrap();
+
+        let mut image = gpt_image(&table, 1000, &options);
+        assert_eq!(&image.get_ref()[4096..4104], &GPT_MAGIC);
+        assert_eq!(&image.get_ref()[999 * 4096..999 * 4096 + 8], &GPT_MAGIC);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(loaded == table);
+        assert!(loaded.is_consistent(&options));
+
+        image.get_mut()[4096 + 16] ^= 1;
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(loaded.loaded_from_backup());
+        assert!(loaded == table);
+    }
+
+    #[test]
+    fn repair_single_copy() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+        let good = image.get_ref().clone();
+
+        image.get_mut()[4095 * 512 + 20] ^= 1;
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(!loaded.loaded_from_backup());
+        loaded.repair_backup(&mut image, &options).unwrap();
+        assert!(image.get_ref() == &good);
+
+        image.get_mut()[512 + 20] ^= 1;
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(loaded.loaded_from_backup());
+        loaded.repair_primary(&mut image, &options).unwrap();
+        assert!(image.get_ref() == &good);
+    }
+
+    #[test]
+    fn used_partitions() {
+        let options = GPTOptions::default();
+        let mut table = test_table(&options);
+        table.delete_partition(31).unwrap();
+        assert_eq!(table.used_partitions().map(|(id, _)| id).collect::<Vec<_>>(), vec![0, 32, 99]);
+        assert_eq!(table.used_partitions().nth(1).unwrap().1.start, Block(420));
+    }
+
+    #[test]
+    fn builder() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        let p = PartitionEntryBuilder::new()
+            .part_type(*types::LINUX_FILESYSTEM)
+            .name("root")
+            .range(Block(40), Block(99))
+            .flags(FLAG_LEGACY_BOOTABLE)
+            .build()
+            .unwrap();
+        // A random PARTUUID is generated
+        assert!(!p.part_id.is_nil());
+        table.set_partition(0, p.clone()).unwrap();
+
+        let mut image = gpt_image(&table, 1000, &options);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert_eq!(loaded.partitions()[0].as_ref(), Some(&p));
+
+        assert!(matches!(PartitionEntryBuilder::new().range(Block(5), Block(4)).build().unwrap_err().error_type(), ErrorType::InvalidRange));
+        assert!(PartitionEntryBuilder::new().range(Block(5), Block(6)).name(&"x".repeat(37)).build().is_err());
+    }
+
+    #[test]
+    fn display() {
+        let mut p = part(40, 99);
+        p.name = String::from("data");
+        assert_eq!(p.to_string(), "\"data\" (Linux filesystem) blocks 40-99 (60 blocks)");
+        p.part_type = UUID::nil();
+        assert_eq!(p.to_string(), "\"data\" (00000000-0000-0000-0000-000000000000) blocks 40-99 (60 blocks)");
+    }
+
+    #[test]
+    fn size_bytes() {
+        let p = part(40, 40);
+        assert_eq!(p.block_count(), 1);
+        assert_eq!(p.size_bytes(512), 512);
+        assert_eq!(p.size_bytes(4096), 4096);
+        assert_eq!(part(40, 99).size_bytes(512), 60 * 512);
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let options = GPTOptions::default();
+        let mut image = gpt_image(&test_table(&options), 4096, &options);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+
+        let mut copy = loaded.clone();
+        copy.delete_partition(0).unwrap();
+        copy.regenerate_disk_guid();
+        assert!(loaded.partitions()[0].is_some());
+        assert!(loaded.disk_guid() != copy.disk_guid());
+        assert_eq!(loaded.used_partitions().count(), 4);
+    }
+
+    #[test]
+    fn equality() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(loaded == table);
+
+        let mut changed = loaded.clone();
+        let mut p = changed.partitions()[0].clone().unwrap();
+        assert_eq!(&p, loaded.partitions()[0].as_ref().unwrap());
+        p.flags = FLAG_REQUIRED;
+        assert!(&p != loaded.partitions()[0].as_ref().unwrap());
+        changed.set_partition(0, p).unwrap();
+        assert!(changed != loaded);
+    }
+
+    #[test]
+    fn diff() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut changed = table.clone();
+        changed.delete_partition(0).unwrap();
+        let mut renamed = changed.partitions()[31].clone().unwrap();
+        renamed.name = String::from("renamed");
+        changed.set_partition(31, renamed.clone()).unwrap();
+        let added = part(1000, 1010);
+        changed.set_partition(50, added.clone()).unwrap();
+
+        assert_eq!(table.diff(&changed), vec![
+            PartitionChange::Removed(0, table.partitions()[0].clone().unwrap()),
+            PartitionChange::Modified(31, table.partitions()[31].clone().unwrap(), renamed),
+            PartitionChange::Added(50, added)
+        ]);
+        assert!(table.header_diff(&changed).is_empty());
+
+        let other = GPTTable::create(4096, &options).unwrap();
+        let header = table.header_diff(&other);
+        assert_eq!(header.disk_guid, Some((table.disk_guid(), other.disk_guid())));
+        assert_eq!(header.usable_range, Some(((Block(27), Block(4069)), (Block(34), Block(4062)))));
+    }
+
+    #[test]
+    fn move_and_resize() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        table.set_partition(0, part(100, 199)).unwrap();
+        table.set_partition(1, part(300, 399)).unwrap();
+
+        table.resize_partition(0, Block(299)).unwrap();
+        assert!(matches!(table.resize_partition(0, Block(300)).unwrap_err().error_type(), ErrorType::Overlap));
+        table.move_partition(1, Block(400)).unwrap();
+        let moved = table.partitions()[1].as_ref().unwrap();
+        assert_eq!((moved.start, moved.end), (Block(400), Block(499)));
+
+        assert!(matches!(table.move_partition(1, Block(950)).unwrap_err().error_type(), ErrorType::OutOfBounds));
+        assert!(matches!(table.move_partition(5, Block(500)).unwrap_err().error_type(), ErrorType::InvalidID));
+        // Does not overflow near the end of the address space
+        assert!(matches!(table.move_partition(0, Block(u64::MAX - 5)).unwrap_err().error_type(), ErrorType::OutOfBounds));
+    }
+
+    #[test]
+    fn swap_partitions() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        table.set_partition(3, part(100, 199)).unwrap();
+        table.swap_partitions(3, 0).unwrap();
+        assert_eq!(table.partitions()[0].as_ref().unwrap().start, Block(100));
+        assert!(table.partitions()[3].is_none());
+        assert!(matches!(table.swap_partitions(0, 128).unwrap_err().error_type(), ErrorType::InvalidID));
+    }
+
+    #[test]
+    fn compact() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        table.set_partition(0, part(100, 109)).unwrap();
+        table.set_partition(2, part(300, 309)).unwrap();
+        table.set_partition(5, part(200, 209)).unwrap();
+
+        // Returns the (old, new) slot of every moved partition, keeping their order
+        assert_eq!(table.compact(), vec![(2, 1), (5, 2)]);
+        assert_eq!(table.used_partitions().map(|(id, p)| (id, p.start.0)).collect::<Vec<_>>(), vec![(0, 100), (1, 300), (2, 200)]);
+        assert_eq!(table.compact(), vec![]);
+    }
+
+    fn mbr_part(bootable: bool, system_id: u8, start_lba: u32, sector_count: u32) -> MBRPartitionEntry {
+        MBRPartitionEntry { bootable, system_id, start_lba, sector_count, ..MBRPartitionEntry::default() }
+    }
+
+    #[test]
+    fn from_mbr() {
+        let options = GPTOptions::default();
+        let mut mbr = MBR::new();
+        mbr.add_partition(mbr_part(true, 0x83, 2048, 2048)).unwrap();
+        mbr.add_partition(mbr_part(false, 0x07, 4096, 4096)).unwrap();
+        let table = GPTTable::from_mbr(&mbr, 10000, &options).unwrap();
+        let parts = table.used_partitions()
+            .map(|(id, p)| (id, p.start.0, p.end.0, types::name_for_type(&p.part_type).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(parts, vec![(0, 2048, 4095, "Linux filesystem"), (1, 4096, 8191, "Microsoft basic data")]);
+
+        // Partitions overlapping the space needed for the GPT can not be converted
+        let mut mbr = MBR::new();
+        mbr.add_partition(mbr_part(true, 0x83, 1, 2048)).unwrap();
+        assert!(GPTTable::from_mbr(&mbr, 10000, &options).is_err());
+        let mut mbr = MBR::new();
+        mbr.add_partition(mbr_part(true, 0x83, 2048, 9000)).unwrap();
+        assert!(GPTTable::from_mbr(&mbr, 10000, &options).is_err());
+    }
+
+    #[test]
+    fn to_mbr() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(100000, &options).unwrap();
+        table.set_partition(0, part(2048, 4095)).unwrap();
+        let mut other = part(4096, 8191);
+        other.part_type = UUID::new_v4();
+        table.set_partition(1, other).unwrap();
+
+        assert!(table.to_mbr(MbrExportMode::Protective, &options).unwrap().is_protective());
+        let hybrid = table.to_mbr(MbrExportMode::Hybrid(vec![0, 1]), &options).unwrap();
+        assert!(hybrid.is_hybrid());
+        let parts = hybrid.used_partitions().map(|(_, p)| (p.system_id, p.start_lba, p.sector_count)).collect::<Vec<_>>();
+        // Unknown types are exported as basic data
+        assert_eq!(parts, vec![(0xEE, 1, 33), (0x83, 2048, 2048), (0x07, 4096, 4096)]);
+
+        assert!(table.to_mbr(MbrExportMode::Hybrid(vec![0, 1, 2]), &options).is_err());
+        assert!(table.to_mbr(MbrExportMode::Hybrid(vec![0, 1, 1, 1]), &options).is_err());
+
+        let mut big = GPTTable::create(1 << 33, &options).unwrap();
+        big.set_partition(0, part(2048, 1 << 32)).unwrap();
+        let err = big.to_mbr(MbrExportMode::Hybrid(vec![0]), &options).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::ExceedsMbrLimit));
+    }
+
+    #[test]
+    fn validate() {
+        let options = GPTOptions::default();
+        let mut image = gpt_image(&test_table(&options), 4096, &options);
+        assert!(GPTTable::validate(&mut image, &options).unwrap().is_ok());
+
+        // Backup partition table
+        image.get_mut()[(4095 - 25) * 512 + 3] ^= 1;
+        assert_eq!(GPTTable::validate(&mut image, &options).unwrap(), GptValidation {
+            primary_header_ok: true,
+            primary_array_ok: true,
+            backup_header_ok: true,
+            backup_array_ok: false
+        });
+
+        // The array can not be checked without an intact header
+        image.get_mut()[512 + 30] ^= 1;
+        let validation = GPTTable::validate(&mut image, &options).unwrap();
+        assert!(!validation.primary_header_ok && !validation.primary_array_ok && validation.backup_header_ok);
+
+        let err = GPTTable::validate(&mut blank_image(4096, 512), &options).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::NoTable));
+    }
+
+    #[test]
+    fn partition_window() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut image = blank_image(4096, 512);
+        image.get_mut()[410 * 512] = 7;
+
+        {
+            let mut window = table.partition_window(31, &mut image, &options).unwrap();
+            assert_eq!((window.start(), window.len()), (410 * 512, 10 * 512));
+            let mut buf = [0u8; 1];
+            window.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[0], 7);
+        }
+        match table.partition_window(5, &mut image, &options) {
+            Err(err) => assert!(matches!(err.error_type(), ErrorType::InvalidID)),
+            Ok(_) => panic!("Empty slot mapped to a window")
+        }
+    }
+
+    #[test]
+    fn uuid_disk_bytes() {
+        let uuid = UUID::parse_str("C12A7328-F81F-11D2-BA4B-00A0C93EC93B").unwrap();
+        // The first three fields are stored little endian
+        let bytes = [0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B];
+        assert_eq!(uuid_to_disk_bytes(uuid), bytes);
+        assert_eq!(uuid_from_disk_bytes(&bytes).unwrap(), uuid);
+
+        let mut buf = Cursor::new(Vec::new());
+        write_uuid(&mut buf, uuid).unwrap();
+        buf.set_position(0);
+        assert_eq!(read_uuid(&mut buf).unwrap(), uuid);
+    }
+
+    #[test]
+    fn utf16_modes() {
+        let mut table = GPTTable::create(1000, &GPTOptions::default()).unwrap();
+        let mut p = part(40, 50);
+        p.name = String::from("abc");
+        table.set_partition(0, p).unwrap();
+        let mut image = gpt_image(&table, 1000, &GPTOptions::default());
+        // Replace the 'b' by a lone high surrogate
+        image.get_mut()[1024 + 56 + 2] = 0x00;
+        image.get_mut()[1024 + 56 + 3] = 0xD8;
+
+        let load = |mode| {
+            let options = GPTOptions { ignore_csum: true, utf16_mode: mode, ..GPTOptions::default() };
+            GPTTable::load(&mut image.clone(), &options)
+        };
+        assert!(matches!(load(Utf16Mode::Strict).unwrap_err().error_type(), ErrorType::UTF16Error));
+        assert_eq!(load(Utf16Mode::Lossy).unwrap().partitions()[0].as_ref().unwrap().name, "a\u{FFFD}c");
+        assert_eq!(load(Utf16Mode::EmptyOnError).unwrap().partitions()[0].as_ref().unwrap().name, "");
+    }
+
+    #[test]
+    fn unterminated_names() {
+        // All 36 units used, so there is no terminating zero
+        let mut buf = Cursor::new(Vec::new());
+        write_utf16_le(&mut buf, &"x".repeat(36)).unwrap();
+        assert_eq!(buf.get_ref().len(), 72);
+        buf.set_position(0);
+        assert_eq!(read_utf16_le(&mut buf, Utf16Mode::Strict).unwrap(), "x".repeat(36));
+    }
+
+    #[test]
+    fn relocate_backup() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(100, &options).unwrap();
+        table.relocate_backup(1000, &options).unwrap();
+        assert_eq!(table.backup_header_lba(), Block(999));
+        assert_eq!(table.usable_range(), (Block(34), Block(966)));
+
+        let mut image = gpt_image(&table, 1000, &options);
+        assert_eq!(&image.get_ref()[999 * 512..999 * 512 + 8], &GPT_MAGIC);
+        assert!(GPTTable::load(&mut image, &options).unwrap() == table);
+        assert!(table.relocate_backup(10, &options).is_err());
+    }
+
+    #[test]
+    fn inverted_ranges() {
+        let mut p = part(40, 50);
+        p.end = Block(30);
+        assert_eq!(p.block_count(), 0);
+        assert_eq!(p.size_bytes(512), 0);
+        let _ = p.to_string();
+        p.start = Block(0);
+        p.end = Block(u64::MAX);
+        assert_eq!(p.block_count(), u64::MAX);
+
+        let options = GPTOptions { ignore_csum: true, ..GPTOptions::default() };
+        let mut table = GPTTable::create(300, &options).unwrap();
+        table.set_partition(0, part(40, 50)).unwrap();
+        let mut image = gpt_image(&table, 300, &options);
+        // Low byte of the first entry's end LBA
+        image.get_mut()[1024 + 40] = 30;
+        assert!(matches!(GPTTable::load(&mut image, &options).unwrap_err().error_type(), ErrorType::InvalidRange));
+    }
+
+    #[test]
+    fn set_disk_size() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(100, &options).unwrap();
+        assert_eq!(table.usable_range(), (Block(34), Block(66)));
+        table.set_disk_size(1000, &options).unwrap();
+        assert_eq!(table.usable_range(), (Block(34), Block(966)));
+
+        table.set_partition(0, part(500, 600)).unwrap();
+        assert!(table.set_disk_size(200, &options).is_err());
+        // A failed resize leaves the table untouched
+        assert_eq!(table.usable_range(), (Block(34), Block(966)));
+    }
+
+    #[test]
+    fn disk_guid() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(100, &options).unwrap();
+        let guid = table.disk_guid();
+        table.regenerate_disk_guid();
+        assert!(table.disk_guid() != guid);
+        table.set_disk_guid(guid);
+        assert_eq!(table.disk_guid(), guid);
+
+        let mut image = gpt_image(&table, 100, &options);
+        assert_eq!(GPTTable::load(&mut image, &options).unwrap().disk_guid(), guid);
+    }
+
+    #[test]
+    fn regenerate_partition_guids() {
+        let options = GPTOptions::default();
+        let before = test_table(&options);
+        let mut table = before.clone();
+        table.regenerate_partition_guids();
+        assert_eq!(table.used_partitions().count(), 4);
+        for ((_, old), (_, new)) in before.used_partitions().zip(table.used_partitions()) {
+            assert!(old.part_id != new.part_id);
+            assert_eq!(old.part_type, new.part_type);
+            assert_eq!((old.start, old.end), (new.start, new.end));
+        }
+    }
+
+    #[test]
+    fn write_single_copy() {
+        let options = GPTOptions::default();
+        let table = GPTTable::create(100, &options).unwrap();
+
+        let mut image = blank_image(100, 512);
+        table.write_primary_only(&mut image, &options).unwrap();
+        assert_eq!(&image.get_ref()[512..520], &GPT_MAGIC);
+        assert!(image.get_ref()[67 * 512..].iter().all(|&b| b == 0));
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(!loaded.loaded_from_backup());
+
+        let mut image = blank_image(100, 512);
+        table.write_backup_only(&mut image, &options).unwrap();
+        assert!(image.get_ref()[..67 * 512].iter().all(|&b| b == 0));
+        assert_eq!(&image.get_ref()[99 * 512..99 * 512 + 8], &GPT_MAGIC);
+    }
+
+    /// Write `table` to an image with the primary partition table at block 3 instead of 2
+    fn shifted_array_image(table: &GPTTable, options: &GPTOptions) -> Cursor<Vec<u8>> {
+        let mut image = gpt_image(table, 4096, options);
+        let array_len = table.partition_count() as usize * 128;
+        let first_usable = table.usable_range().0;
+        {
+            let buf = image.get_mut();
+            buf.copy_within(1024..1024 + array_len, 1536);
+            let header = &mut buf[512..512 + 92];
+            LittleEndian::write_u64(&mut header[40..48], first_usable.0 + 1);
+            LittleEndian::write_u64(&mut header[72..80], 3);
+            LittleEndian::write_u32(&mut header[16..20], 0);
+            let crc = CRC32::new().checksum(header);
+            LittleEndian::write_u32(&mut header[16..20], crc);
+        }
+        image
+    }
+
+    #[test]
+    fn keeps_partition_table_start() {
+        let options = GPTOptions::default();
+        let mut image = shifted_array_image(&test_table(&options), &options);
+
+        let strict = GPTOptions { strict_layout: true, ..GPTOptions::default() };
+        let err = GPTTable::load(&mut image, &strict).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::InvalidPartitionTableStart(Block(3))));
+
+        let mut table = GPTTable::load(&mut image, &options).unwrap();
+        assert_eq!(table.write_plan(&options)[1], (3 * 512, 100 * 128));
+        // Growing the array keeps it in place, so the usable range starts one block later
+        table.set_partition_count(128, &options).unwrap();
+        assert_eq!(table.usable_range().0, Block(35));
+
+        let mut written = blank_image(4096, 512);
+        table.write(&mut written, &options).unwrap();
+        assert_eq!(LittleEndian::read_u64(&written.get_ref()[512 + 72..512 + 80]), 3);
+        written.set_position(0);
+        assert!(GPTTable::load(&mut written, &options).unwrap() == table);
+    }
+
+    #[test]
+    fn header_lbas() {
+        let options = GPTOptions::default();
+        let table = GPTTable::create(100, &options).unwrap();
+        assert_eq!(table.primary_header_lba(), Block(1));
+        assert_eq!(table.backup_header_lba(), Block(99));
+        assert!(table.headers_point_correctly(100));
+        assert!(!table.headers_point_correctly(1000));
+        assert!(!table.headers_point_correctly(0));
+    }
+
+    #[test]
+    fn update_partition_arrays() {
+        let options = GPTOptions::default();
+        let mut table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+
+        let mut p = table.partitions()[31].clone().unwrap();
+        p.set_name("renamed").unwrap();
+        table.set_partition(31, p).unwrap();
+        table.update_partition_arrays(&mut image, &options).unwrap();
+
+        assert!(GPTTable::validate(&mut image, &options).unwrap().is_ok());
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert_eq!(loaded.partitions()[31].as_ref().unwrap().name, "renamed");
+        assert!(loaded == table);
+        let expected = gpt_image(&table, 4096, &options);
+        assert!(image.get_ref() == expected.get_ref());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+
+        let json = ::serde_json::to_value(&table).unwrap();
+        // UUIDs are hyphenated strings and blocks plain numbers
+        let (first_usable, _) = table.usable_range();
+        assert_eq!(json["gpt_uuid"], table.disk_guid().hyphenated().to_string());
+        assert_eq!(json["first_usable"], first_usable.0);
+        let part = &json["partitions"][31];
+        assert_eq!(part["part_type"], "0fc63daf-8483-4772-8e79-3d69d8477de4");
+        assert_eq!(part["start"], 410);
+
+        let back: GPTTable = ::serde_json::from_value(json).unwrap();
+        assert!(back == table);
+    }
+
+    #[test]
+    fn into_io_error() {
+        use std::io::{self, ErrorKind};
+
+        let options = GPTOptions::default();
+        let err: io::Error = GPTTable::load(&mut blank_image(8, 512), &options).unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        // The original error is kept as the inner error
+        assert!(err.get_ref().unwrap().downcast_ref::<GPTError>().is_some());
+
+        let err: io::Error = PartitionEntryBuilder::new().name(&"x".repeat(40)).build().unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err: io::Error = GPTTable::create(10, &options).unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    /// Records the region of every write, to compare them with the write plan
+    struct RecordingWriter {
+        inner: Cursor<Vec<u8>>,
+        writes: Vec<(u64, u64)>
+    }
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            self.writes.push((self.inner.position(), buf.len() as u64));
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for RecordingWriter {
+        fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn write_plan() {
+        let options = GPTOptions { protective_mbr: true, ..GPTOptions::default() };
+        let table = GPTTable::create(200, &options).unwrap();
+        let plan = table.write_plan(&options);
+        assert_eq!(plan, vec![(0, 512), (512, 512), (1024, 16384), (199 * 512, 512), (167 * 512, 16384)]);
+
+        let mut image = RecordingWriter { inner: blank_image(200, 512), writes: Vec::new() };
+        table.write(&mut image, &options).unwrap();
+        for &(offset, len) in &image.writes {
+            assert!(plan.iter().any(|&(start, size)| offset >= start && offset + len <= start + size),
+                    "write of {} bytes at {} is not planned", len, offset);
+        }
+    }
+
+    #[test]
+    fn find_by_partuuid_and_name() {
+        let options = GPTOptions::default();
+        let mut table = test_table(&options);
+        let mut p = table.partitions()[32].clone().unwrap();
+        p.set_name("root").unwrap();
+        table.set_partition(32, p.clone()).unwrap();
+
+        assert_eq!(table.find_by_partuuid(&p.part_id).unwrap().0, 32);
+        assert!(table.find_by_partuuid(&UUID::new_v4()).is_none());
+        assert_eq!(table.find_by_name("root").unwrap().0, 32);
+        // Names are matched exactly, the first match wins
+        assert!(table.find_by_name("Root").is_none());
+        assert_eq!(table.find_by_name("chunked").unwrap().0, 0);
+    }
+
+    #[test]
+    fn find_by_type() {
+        let options = GPTOptions::default();
+        let mut table = test_table(&options);
+        let mut swap = table.partitions()[31].clone().unwrap();
+        swap.part_type = *types::LINUX_SWAP;
+        table.set_partition(31, swap).unwrap();
+
+        let ids = table.find_by_type(&types::LINUX_FILESYSTEM).into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 32, 99]);
+        assert_eq!(table.find_by_type(&types::LINUX_SWAP).len(), 1);
+        assert!(table.find_by_type(&types::EFI_SYSTEM).is_empty());
+    }
+
+    #[test]
+    fn erase() {
+        let options = GPTOptions { protective_mbr: true, ..GPTOptions::default() };
+        let table = GPTTable::create(200, &options).unwrap();
+        let mut image = gpt_image(&table, 200, &options);
+
+        GPTTable::erase(&mut image, 200, true, &options).unwrap();
+        assert!(!GPTTable::exists(&mut image, &options).unwrap());
+        assert_eq!(&image.get_ref()[510..512], &[0x55, 0xAA]);
+        assert!(image.get_ref()[512..].iter().all(|&b| b == 0));
+        assert_eq!(image.get_ref().len(), 200 * 512);
+
+        GPTTable::erase(&mut image, 200, false, &options).unwrap();
+        assert!(image.get_ref().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn erase_large_partition_table() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        table.set_partition_count(1024, &options).unwrap();
+        let mut image = gpt_image(&table, 1000, &options);
+        // Data behind the partition tables is kept
+        image.get_mut()[600 * 512] = 0xAA;
+
+        GPTTable::erase(&mut image, 1000, false, &options).unwrap();
+        assert_eq!(image.get_ref()[600 * 512], 0xAA);
+        image.get_mut()[600 * 512] = 0;
+        assert!(image.get_ref().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn cross_validate() {
+        let options = GPTOptions::default();
+        let mut table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+        assert!(GPTTable::cross_validate(&mut image, &options).unwrap().is_ok());
+        assert!(GPTTable::backup_matches_primary(&mut image, &options).unwrap());
+
+        // Both copies are valid on their own, but disagree
+        table.regenerate_disk_guid();
+        table.write_backup_only(&mut image, &options).unwrap();
+        let validation = GPTTable::cross_validate(&mut image, &options).unwrap();
+        assert!(!validation.disk_guid_match);
+        assert!(validation.locations_match && validation.usable_range_match);
+        assert!(GPTTable::validate(&mut image, &options).unwrap().is_ok());
+    }
+
+    #[test]
+    fn copy_partition() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(400, &options).unwrap();
+        // Larger than one chunk, and not a multiple of it
+        table.set_partition(0, part(34, 233)).unwrap();
+        let data = (0..400 * 512).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut src = Cursor::new(data.clone());
+        let mut dst = blank_image(400, 512);
+
+        assert_eq!(copy_partition_data(&table, 0, &mut src, &mut dst, &options).unwrap(), 200 * 512);
+        assert_eq!(&dst.get_ref()[34 * 512..234 * 512], &data[34 * 512..234 * 512]);
+        assert!(dst.get_ref()[..34 * 512].iter().all(|&b| b == 0));
+        assert!(dst.get_ref()[234 * 512..].iter().all(|&b| b == 0));
+
+        assert!(copy_partition_data(&table, 0, &mut blank_image(100, 512), &mut dst, &options).is_err());
+        assert!(copy_partition_data(&table, 1, &mut src, &mut dst, &options).is_err());
+    }
+
+    #[test]
+    fn checksum_error_region() {
+        let options = GPTOptions::default();
+        let table = GPTTable::create(200, &options).unwrap();
+        let mut primary_only = blank_image(200, 512);
+        table.write_primary_only(&mut primary_only, &options).unwrap();
+        let both = gpt_image(&table, 200, &options);
+        let load_flipped = |image: &Cursor<Vec<u8>>, offsets: &[usize]| {
+            let mut image = image.clone();
+            for &offset in offsets {
+                image.get_mut()[offset] ^= 1;
+            }
+            GPTTable::load(&mut image, &options)
+        };
+
+        let err = load_flipped(&primary_only, &[512 + 60]).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::HeaderChecksumError));
+        assert!(err.is_checksum_error());
+        let err = load_flipped(&primary_only, &[1024 + 200]).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::PartitionArrayChecksumError));
+        // Different regions failed in both copies
+        let err = load_flipped(&both, &[1024 + 200, 199 * 512 + 60]).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::ChecksumError));
+        assert!(load_flipped(&both, &[1024 + 200]).unwrap().loaded_from_backup());
+    }
+
+    #[test]
+    fn repair_from() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+        let good = image.get_ref().clone();
+        for b in &mut image.get_mut()[1024..1024 + 128] {
+            *b = 0xAB;
+        }
+
+        let mut read = image.clone();
+        GPTTable::repair_from(&mut read, &mut image, &options).unwrap();
+        assert!(image.get_ref() == &good);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert!(!loaded.loaded_from_backup());
+        assert!(loaded == table);
+    }
+
+    /// Only hands out a few bytes per read, and can not seek
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            let len = cmp::min(buf.len(), 100);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn load_buffered() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let image = gpt_image(&table, 4096, &options);
+
+        let loaded = GPTTable::load_buffered(&mut Trickle(image.clone()), 4096, &options).unwrap();
+        assert!(loaded == table);
+        assert!(GPTTable::load_buffered(&mut Trickle(image), 20, &options).is_err());
+        assert!(GPTTable::load_buffered(&mut Trickle(blank_image(8, 512)), 8, &options).is_err());
+    }
+
+    #[test]
+    fn load_buffered_checks_header_first() {
+        let options = GPTOptions::default();
+        let hint = 1 << 40;
+        let mut image = gpt_image(&GPTTable::create(200, &options).unwrap(), 200, &options);
+        let header_start = 512;
+        LittleEndian::write_u64(&mut image.get_mut()[header_start + 72..header_start + 80], hint - 1);
+
+        // Rejected before anything beyond the header is read
+        let mut stream = Trickle(image.clone());
+        let err = GPTTable::load_buffered(&mut stream, hint, &options).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::HeaderChecksumError));
+        assert_eq!(stream.0.position(), 1024);
+
+        // With a valid checksum, the partition table still has to end before the first usable block
+        {
+            let header = &mut image.get_mut()[header_start..header_start + 92];
+            LittleEndian::write_u32(&mut header[16..20], 0);
+            let crc = options.checksum(header);
+            LittleEndian::write_u32(&mut header[16..20], crc);
+        }
+        let mut stream = Trickle(image);
+        let err = GPTTable::load_buffered(&mut stream, hint, &options).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::InvalidPartitionTableStart(_)));
+        assert_eq!(stream.0.position(), 1024);
+    }
+
+    #[test]
+    fn partition_id_bounds() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(200, &options).unwrap();
+        assert!(table.set_partition(128, part(40, 50)).is_err());
+        assert!(table.set_partition(127, part(40, 50)).is_ok());
+        assert!(table.delete_partition(128).is_err());
+        assert!(table.delete_partition(127).is_ok());
+
+        table.set_partition_count(0, &options).unwrap();
+        assert!(matches!(table.set_partition(0, part(40, 50)).unwrap_err().error_type(), ErrorType::InvalidID));
+        assert!(table.delete_partition(0).is_err());
+    }
+
+    #[test]
+    fn options_block_size() {
+        for &block_size in &[0u16, 1, 256, 513, 1000] {
+            match GPTOptions::new(block_size) {
+                Err(e) => assert!(matches!(e.error_type(), ErrorType::InvalidBlockSize(_))),
+                Ok(_) => panic!("block size {} accepted", block_size)
+            }
+        }
+        assert_eq!(GPTOptions::new(512).unwrap().block_size, 512);
+        assert_eq!(GPTOptions::new(4096).unwrap().block_size, 4096);
+    }
+
+    #[test]
+    fn partitions_by_start() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(200, &options).unwrap();
+        table.set_partition(0, part(100, 110)).unwrap();
+        table.set_partition(3, part(40, 50)).unwrap();
+        table.set_partition(5, part(60, 70)).unwrap();
+        let ids = table.partitions_by_start().into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 5, 0]);
+    }
+
+    #[test]
+    fn usage() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(200, &options).unwrap();
+        // The usable range is 34 to 166, the partitions leave two gaps
+        table.set_partition(0, part(34, 50)).unwrap();
+        table.set_partition(1, part(60, 100)).unwrap();
+        table.set_partition(2, part(120, 166)).unwrap();
+
+        let usage = table.usage(&options);
+        assert_eq!(usage.total_usable_blocks, 133);
+        assert_eq!(usage.free_blocks, 9 + 19);
+        assert_eq!(usage.used_blocks + usage.free_blocks, usage.total_usable_blocks);
+        assert_eq!(usage.fragment_count, 2);
+        assert_eq!(usage.largest_free_blocks, 19);
+
+        let usage = GPTTable::create(200, &options).unwrap().usage(&options);
+        assert_eq!((usage.used_blocks, usage.free_blocks, usage.fragment_count), (0, 133, 1));
+    }
+
+    #[test]
+    fn write_progress() {
+        let options = GPTOptions { protective_mbr: true, ..GPTOptions::default() };
+        let table = GPTTable::create(200, &options).unwrap();
+        let mut calls = Vec::new();
+        table.write_with_progress(&mut blank_image(200, 512), &options, |done, total| calls.push((done, total))).unwrap();
+        // Protective MBR, then each copy with its header and partition array
+        let total = 512 + 2 * (512 + 16384);
+        assert_eq!(calls, vec![(512, total), (512 + 512 + 16384, total), (total, total)]);
+    }
+
+    #[test]
+    fn names_are_little_endian() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(200, &options).unwrap();
+        let mut p = part(34, 50);
+        p.set_name("A\u{20AC}").unwrap();
+        table.set_partition(0, p).unwrap();
+        let image = gpt_image(&table, 200, &options);
+        assert_eq!(&image.get_ref()[1024 + 56..1024 + 62], &[0x41, 0x00, 0xAC, 0x20, 0, 0]);
+    }
+
+    #[test]
+    fn shrink_partition_array() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(200, &options).unwrap();
+        table.set_partition(0, part(34, 50)).unwrap();
+        table.set_partition(2, part(60, 70)).unwrap();
+        // Rounded up to fill a whole block
+        assert_eq!(table.shrink_partition_array(&options), 4);
+        assert_eq!(table.partition_count(), 4);
+        assert_eq!(table.usable_range(), (Block(3), Block(197)));
+        assert!(table.set_partition(5, part(80, 90)).is_err());
+
+        let mut image = gpt_image(&table, 200, &options);
+        assert!(GPTTable::load(&mut image, &options).unwrap() == table);
+    }
+
+    #[test]
+    fn partition_entry_bytes() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let image = gpt_image(&table, 4096, &options);
+        // Same bytes as in the partition array on disk
+        let entry = table.partition_entry_bytes(31, &options).unwrap();
+        assert_eq!(&entry[..], &image.get_ref()[1024 + 31 * 128..1024 + 32 * 128]);
+        assert!(table.partition_entry_bytes(1, &options).unwrap().iter().all(|&b| b == 0));
+        assert!(matches!(table.partition_entry_bytes(100, &options).unwrap_err().error_type(), ErrorType::InvalidID));
+    }
+
+    #[test]
+    fn snapshot_restores_overwritten_regions() {
+        let options = GPTOptions::default();
+        let old = GPTTable::create(4096, &options).unwrap();
+        let mut image = gpt_image(&old, 4096, &options);
+        let before = image.get_ref().clone();
+
+        // Backup GPT at a different place than the old one, with the old one still there
+        let mut new = test_table(&options);
+        new.relocate_backup(4000, &options).unwrap();
+        let snapshot = new.snapshot_raw(&mut image, &options).unwrap();
+        // Primary header and array are merged into one region, the backup ones into another
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].0, 512);
+        assert_eq!(snapshot[1].0 + snapshot[1].1.len() as u64, 4000 * 512);
+
+        new.write(&mut image, &options).unwrap();
+        assert!(image.get_ref() != &before);
+        for (offset, bytes) in snapshot {
+            image.seek(SeekFrom::Start(offset)).unwrap();
+            image.write_all(&bytes).unwrap();
+        }
+        assert!(image.get_ref() == &before);
+    }
+
+    #[test]
+    fn larger_header_size() {
+        let options = GPTOptions::default();
+        let mut image = gpt_image(&GPTTable::create(200, &options).unwrap(), 200, &options);
+        // Grow both headers, with a non-zero byte after the standard fields
+        let resize_headers = |image: &mut Cursor<Vec<u8>>, size: u32| {
+            for &offset in &[512, 199 * 512] {
+                let header = &mut image.get_mut()[offset..offset + 512];
+                header[92] = 0xAB;
+                LittleEndian::write_u32(&mut header[12..16], size);
+                LittleEndian::write_u32(&mut header[16..20], 0);
+                let crc = options.checksum(&header[..size as usize]);
+                LittleEndian::write_u32(&mut header[16..20], crc);
+            }
+        };
+
+        resize_headers(&mut image, 96);
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        assert_eq!(loaded.header_size(), 96);
+        let mut written = gpt_image(&loaded, 200, &options);
+        assert_eq!(&written.get_ref()[512..512 + 96], &image.get_ref()[512..512 + 96]);
+        assert_eq!(GPTTable::load(&mut written, &options).unwrap().header_size(), 96);
+
+        resize_headers(&mut image, 90);
+        assert!(GPTTable::load(&mut image, &options).is_err());
+    }
+
+    #[test]
+    fn stale_backup() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(200, &options).unwrap();
+        let mut image = gpt_image(&table, 200, &options);
+        assert!(GPTTable::backup_matches_primary(&mut image, &options).unwrap());
+
+        table.set_partition(0, part(40, 50)).unwrap();
+        table.write_primary_only(&mut image, &options).unwrap();
+        assert!(!GPTTable::backup_matches_primary(&mut image, &options).unwrap());
+        table.write_backup_only(&mut image, &options).unwrap();
+        assert!(GPTTable::backup_matches_primary(&mut image, &options).unwrap());
+
+        let mut corrupt = image.clone();
+        corrupt.get_mut()[199 * 512 + 60] ^= 1;
+        assert!(!GPTTable::backup_matches_primary(&mut corrupt, &options).unwrap());
+        let mut corrupt = image.clone();
+        corrupt.get_mut()[512 + 60] ^= 1;
+        assert!(matches!(GPTTable::backup_matches_primary(&mut corrupt, &options).unwrap_err().error_type(),
+                         ErrorType::HeaderChecksumError));
+        assert!(GPTTable::backup_matches_primary(&mut blank_image(200, 512), &options).is_err());
+    }
+
+    #[test]
+    fn huge_partition_count() {
+        let options = GPTOptions { ignore_csum: true, ..GPTOptions::default() };
+        let mut image = gpt_image(&GPTTable::create(200, &options).unwrap(), 200, &options);
+        LittleEndian::write_u32(&mut image.get_mut()[512 + 80..512 + 84], u32::MAX);
+        assert!(matches!(GPTTable::load(&mut image, &options).unwrap_err().error_type(),
+                         ErrorType::InvalidPartitionCount(u32::MAX)));
+        // The header is not intact, so its partition table is never read
+        assert!(GPTTable::backup_matches_primary(&mut image, &GPTOptions::default()).is_err());
+    }
+
+    #[test]
+    fn preserves_reserved_header_bytes() {
+        let options = GPTOptions::default();
+        let table = GPTTable::create(200, &options).unwrap();
+        let mut image = gpt_image(&table, 200, &options);
+        {
+            let header = &mut image.get_mut()[512..1024];
+            header[20..24].copy_from_slice(&[1, 2, 3, 4]);
+            header[92..96].copy_from_slice(&[5, 6, 7, 8]);
+            LittleEndian::write_u32(&mut header[12..16], 96);
+            LittleEndian::write_u32(&mut header[16..20], 0);
+            let crc = options.checksum(&header[..96]);
+            LittleEndian::write_u32(&mut header[16..20], crc);
+        }
+
+        let loaded = GPTTable::load(&mut image, &options).unwrap();
+        let mut written = gpt_image(&loaded, 200, &options);
+        assert_eq!(&written.get_ref()[512..512 + 96], &image.get_ref()[512..512 + 96]);
+        // The backup gets the same reserved bytes
+        assert_eq!(&written.get_ref()[199 * 512 + 20..199 * 512 + 24], &[1, 2, 3, 4]);
+        assert_eq!(&written.get_ref()[199 * 512 + 92..199 * 512 + 96], &[5, 6, 7, 8]);
+        assert!(GPTTable::validate(&mut written, &options).unwrap().is_ok());
+
+        // New tables have them zeroed
+        let fresh = gpt_image(&table, 200, &options);
+        assert_eq!(&fresh.get_ref()[512 + 20..512 + 24], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn free_regions_iter() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(1000, &options).unwrap();
+        assert_eq!(table.free_regions_iter().collect::<Vec<_>>(), vec![(Block(34), Block(966))]);
+
+        // Unsorted, overlapping and touching the ends of the usable range
+        for (id, &(start, end)) in [(500, 600), (34, 40), (550, 700), (800, 966), (42, 42)].iter().enumerate() {
+            table.set_partition(id as u64, part(start, end)).unwrap();
+        }
+        let free = table.free_regions_iter().collect::<Vec<_>>();
+        assert_eq!(free, vec![(Block(41), Block(41)), (Block(43), Block(499)), (Block(701), Block(799))]);
+        assert_eq!(free, table.free_regions());
+
+        let mut iter = table.free_regions_iter();
+        assert_eq!(iter.by_ref().count(), 3);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn load_from_backup() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+        let mut image = gpt_image(&table, 4096, &options);
+        for b in &mut image.get_mut()[..34 * 512] {
+            *b = 0;
+        }
+        assert!(GPTTable::load(&mut image.clone(), &options).is_err());
+
+        let loaded = GPTTable::load_from_backup(&mut image, 4096, &options).unwrap();
+        assert!(loaded == table);
+        assert!(loaded.loaded_from_backup());
+        assert_eq!(loaded.primary_header_lba(), Block(1));
+
+        assert!(GPTTable::load_from_backup(&mut image, 1, &options).is_err());
+        image.get_mut()[4095 * 512 + 50] ^= 1;
+        assert!(GPTTable::load_from_backup(&mut image, 4096, &options).is_err());
+    }
+
+    #[test]
+    fn partition_flags() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(300, &options).unwrap();
+        let mut p = part(40, 50);
+        p.flags = FLAG_REQUIRED;
+        table.set_partition(0, p).unwrap();
+
+        let flags = table.partition_flags(0).unwrap();
+        table.set_partition_flags(0, flags | FLAG_LEGACY_BOOTABLE).unwrap();
+        assert_eq!(table.partition_flags(0), Some(FLAG_REQUIRED | FLAG_LEGACY_BOOTABLE));
+        assert!(table.partitions()[0].as_ref().unwrap().is_legacy_bootable());
+
+        // Empty and out of range slots have no flags
+        assert_eq!(table.partition_flags(1), None);
+        assert!(table.set_partition_flags(1, FLAG_REQUIRED).is_err());
+        assert!(table.set_partition_flags(1000, FLAG_REQUIRED).is_err());
+    }
+
+    struct Zero;
+
+    impl Crc32 for Zero {
+        fn update(&mut self, _buf: &[u8]) {}
+
+        fn sum(&self) -> u32 {
+            0
+        }
+    }
+
+    #[test]
+    fn swappable_crc32() {
+        // The standard check value of CRC-32
+        let mut crc = crc32();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.sum(), 0xCBF43926);
+
+        let options = GPTOptions::default();
+        let mut image = gpt_image(&GPTTable::create(300, &options).unwrap(), 300, &options);
+        let zero = GPTOptions { crc32: || Box::new(Zero), ..GPTOptions::default() };
+        assert!(GPTTable::load(&mut image, &zero).unwrap_err().is_checksum_error());
+        let ignore = GPTOptions { ignore_csum: true, ..zero };
+        assert!(GPTTable::load(&mut image, &ignore).is_ok());
+    }
+
+    #[test]
+    fn chunked_array_crc_matches_single_pass() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+
+        let mut buf = Cursor::new(Vec::new());
+        let crc = table.write_partitions(&mut buf, &options).unwrap();
+        assert_eq!(buf.get_ref().len(), 100 * 128);
+        assert_eq!(crc, CRC32::new().checksum(buf.get_ref()));
+        assert_eq!(crc, table.partition_array_crc(&options));
+    }
+
+    /// Checksums by summing up all bytes, to tell it apart from the default implementation
+    struct ByteSum(u32);
+
+    impl Crc32 for ByteSum {
+        fn update(&mut self, buf: &[u8]) {
+            for &b in buf {
+                self.0 = self.0.wrapping_add(b as u32);
+            }
+        }
+
+        fn sum(&self) -> u32 {
+            self.0
+        }
+    }
+
+    fn byte_sum() -> Box<dyn Crc32> {
+        Box::new(ByteSum(0))
+    }
+
+    #[test]
+    fn chunked_array_crc_uses_custom_implementation() {
+        let options = GPTOptions { crc32: byte_sum, ..GPTOptions::default() };
+        let table = test_table(&options);
+
+        let mut buf = Cursor::new(Vec::new());
+        let crc = table.write_partitions(&mut buf, &options).unwrap();
+        assert_eq!(crc, options.checksum(buf.get_ref()));
+        assert_ne!(crc, CRC32::new().checksum(buf.get_ref()));
+    }
+
+    #[test]
+    fn write_handles_short_writes() {
+        let options = GPTOptions::default();
+        let table = test_table(&options);
+
+        let mut image = ShortWriter::new(blank_image(4096, 512), 7);
+        table.write(&mut image, &options).unwrap();
+        let mut image = image.into_inner();
+        image.set_position(0);
+        assert!(GPTTable::load(&mut image, &options).unwrap() == table);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let options = GPTOptions::default();
+        let image = gpt_image(&test_table(&options), 4096, &options);
+
+        // Cut off in the middle of the primary partition array
+        let mut image = Cursor::new(image.into_inner()[..3 * 512].to_vec());
+        let err = GPTTable::load(&mut image, &options).unwrap_err();
+        assert!(matches!(err.error_type(), ErrorType::TruncatedStream));
+    }
+}
@@ -0,0 +1,95 @@
+use super::{GPTOptions, GPTError, ErrorType, GPT_MAGIC, MAX_PART_COUNT, UUID, uuid_from_disk_bytes};
+use super::super::util::Block;
+use super::byteorder::{ByteOrder, LittleEndian};
+use std::io::{Read, Seek, SeekFrom};
+
+/// The fields of a GPT header needed to locate and verify its partition table, read without
+/// parsing the partition entries
+pub struct RawHeader {
+    /// Whether the checksum of the header is correct
+    pub header_ok: bool,
+    /// Location of this header
+    pub my_lba: Block,
+    /// Location of the other copy of the header
+    pub alternate_lba: Block,
+    /// First usable block for data
+    pub first_usable: Block,
+    /// Last usable block for data
+    pub last_usable: Block,
+    /// The UUID of the disk
+    pub disk_guid: UUID,
+    /// Location of the partition table
+    pub part_start: Block,
+    /// Number of entries in the partition table
+    pub part_count: u32,
+    /// Size of a single partition entry
+    pub part_size: u32,
+    /// Checksum of the partition table
+    pub part_crc: u32
+}
+
+impl RawHeader {
+    /// Read the header located at `lba`. Returns None if there is no GPT header.
+    pub fn read<T: Read + Seek>(read: &mut T, lba: Block, options: &GPTOptions) -> Result<Option<RawHeader>, GPTError> {
+        let mut buf = vec![0u8; options.block_size as usize];
+        read.seek(SeekFrom::Start(lba.to_bytes(options.block_size)))?;
+        read.read_exact(&mut buf)?;
+
+        if buf[0..8] != GPT_MAGIC {
+            return Ok(None);
+        }
+
+        let hlen = LittleEndian::read_u32(&buf[12..16]) as usize;
+        let header_ok = hlen >= 92 && hlen <= buf.len() && {
+            let crc = LittleEndian::read_u32(&buf[16..20]);
+            LittleEndian::write_u32(&mut buf[16..20], 0);
+            options.checksum(&buf[..hlen]) == crc
+        };
+
+        let mut disk_guid = [0u8; 16];
+        disk_guid.copy_from_slice(&buf[56..72]);
+
+        Ok(Some(RawHeader {
+            header_ok,
+            my_lba: Block(LittleEndian::read_u64(&buf[24..32])),
+            alternate_lba: Block(LittleEndian::read_u64(&buf[32..40])),
+            first_usable: Block(LittleEndian::read_u64(&buf[40..48])),
+            last_usable: Block(LittleEndian::read_u64(&buf[48..56])),
+            disk_guid: uuid_from_disk_bytes(&disk_guid)?,
+            part_start: Block(LittleEndian::read_u64(&buf[72..80])),
+            part_count: LittleEndian::read_u32(&buf[80..84]),
+            part_size: LittleEndian::read_u32(&buf[84..88]),
+            part_crc: LittleEndian::read_u32(&buf[88..92])
+        }))
+    }
+
+    /// Read the partition table the header points to
+    ///
+    /// The header has to be intact and describe a partition table this library supports, so a
+    /// corrupt header can not cause a huge allocation.
+    pub fn read_partitions<T: Read + Seek>(&self, read: &mut T, options: &GPTOptions) -> Result<Vec<u8>, GPTError> {
+        if !self.header_ok {
+            return Err(GPTError::new(ErrorType::HeaderChecksumError));
+        }
+        if self.part_size != 128 {
+            return Err(GPTError::new(ErrorType::UnsupportedEntrySize(self.part_size)));
+        }
+        let len = match (self.part_count as usize).checked_mul(self.part_size as usize) {
+            Some(len) if self.part_count <= MAX_PART_COUNT => len,
+            _ => return Err(GPTError::new(ErrorType::InvalidPartitionCount(self.part_count)))
+        };
+        let mut buf = vec![0u8; len];
+        read.seek(SeekFrom::Start(self.part_start.to_bytes(options.block_size)))?;
+        read.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Checks whether the header is intact and the partition table matches its checksum
+    pub fn partitions_ok<T: Read + Seek>(&self, read: &mut T, options: &GPTOptions) -> Result<bool, GPTError> {
+        if !self.header_ok {
+            return Ok(false);
+        }
+        let buf = self.read_partitions(read, options)?;
+        Ok(options.checksum(&buf) == self.part_crc)
+    }
+}
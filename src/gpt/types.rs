@@ -0,0 +1,170 @@
+use super::UUID;
+
+// The presets are looked up in TYPES, so every GUID is only written down once
+lazy_static! {
+    /// EFI System Partition
+    pub static ref EFI_SYSTEM: UUID = type_for_name("EFI System Partition").unwrap();
+    /// BIOS boot partition, used by GRUB on BIOS systems
+    pub static ref BIOS_BOOT: UUID = type_for_name("BIOS boot partition").unwrap();
+    /// Linux filesystem data
+    pub static ref LINUX_FILESYSTEM: UUID = type_for_name("Linux filesystem").unwrap();
+    /// Linux swap
+    pub static ref LINUX_SWAP: UUID = type_for_name("Linux swap").unwrap();
+    /// Linux LVM physical volume
+    pub static ref LINUX_LVM: UUID = type_for_name("Linux LVM").unwrap();
+    /// Microsoft basic data, also used for FAT and exFAT partitions
+    pub static ref MICROSOFT_BASIC_DATA: UUID = type_for_name("Microsoft basic data").unwrap();
+    /// Microsoft reserved partition
+    pub static ref MICROSOFT_RESERVED: UUID = type_for_name("Microsoft reserved").unwrap();
+}
+
+/// Broad classification of partition types, e.g. for grouping them in a user interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TypeCategory {
+    /// EFI System Partition
+    Efi,
+    /// BIOS boot partition
+    BiosBoot,
+    /// Linux data partitions
+    Linux,
+    /// Swap space of any operating system
+    Swap,
+    /// LVM physical volumes
+    Lvm,
+    /// Software RAID members
+    Raid,
+    /// Windows partitions
+    Windows,
+    /// macOS partitions
+    Apple,
+    /// BSD partitions
+    Bsd,
+    /// Known types not fitting any other category
+    Other,
+    /// Types not known to this library
+    Unknown
+}
+
+/// Partition type GUIDs together with their human readable names and categories
+const TYPES: &[(&str, &str, TypeCategory)] = &[
+    ("C12A7328-F81F-11D2-BA4B-00A0C93EC93B", "EFI System Partition", TypeCategory::Efi),
+    ("024DEE41-33E7-11D3-9D69-0008C781F39F", "MBR partition scheme", TypeCategory::Other),
+    ("21686148-6449-6E6F-744E-656564454649", "BIOS boot partition", TypeCategory::BiosBoot),
+    ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "Linux filesystem", TypeCategory::Linux),
+    ("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F", "Linux swap", TypeCategory::Swap),
+    ("E6D6D379-F507-44C2-A23C-238F2A3DF928", "Linux LVM", TypeCategory::Lvm),
+    ("A19D880F-05FC-4D3B-A006-743F0F84911E", "Linux RAID", TypeCategory::Raid),
+    ("4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709", "Linux root (x86-64)", TypeCategory::Linux),
+    ("933AC7E1-2EB4-4F13-B844-0E14E2AEF915", "Linux home", TypeCategory::Linux),
+    ("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7", "Microsoft basic data", TypeCategory::Windows),
+    ("E3C9E316-0B5C-4DB8-817D-F92DF00215AE", "Microsoft reserved", TypeCategory::Windows),
+    ("DE94BBA4-06D1-4D40-A16A-BFD50179D6AC", "Windows recovery environment", TypeCategory::Windows),
+    ("48465300-0000-11AA-AA11-00306543ECAC", "Apple HFS+", TypeCategory::Apple),
+    ("7C3457EF-0000-11AA-AA11-00306543ECAC", "Apple APFS", TypeCategory::Apple),
+    ("83BD6B9D-7F41-11DC-BE0B-001560B84F0F", "FreeBSD boot", TypeCategory::Bsd),
+    ("516E7CB4-6ECF-11D6-8FF8-00022D09712B", "FreeBSD data", TypeCategory::Bsd),
+    ("516E7CB5-6ECF-11D6-8FF8-00022D09712B", "FreeBSD swap", TypeCategory::Swap),
+    ("516E7CB6-6ECF-11D6-8FF8-00022D09712B", "FreeBSD UFS", TypeCategory::Bsd),
+    ("516E7CBA-6ECF-11D6-8FF8-00022D09712B", "FreeBSD ZFS", TypeCategory::Bsd),
+];
+
+/// Get the human readable name of a partition type
+///
+/// Returns None if the type is not known
+pub fn name_for_type(uuid: &UUID) -> Option<&'static str> {
+    TYPES.iter()
+        .find(|&&(id, _, _)| UUID::parse_str(id).ok().as_ref() == Some(uuid))
+        .map(|&(_, name, _)| name)
+}
+
+/// Get the partition type for a human readable name as returned by `name_for_type`
+///
+/// Returns None if no type with that name is known
+pub fn type_for_name(name: &str) -> Option<UUID> {
+    TYPES.iter()
+        .find(|&&(_, n, _)| n == name)
+        .and_then(|&(id, _, _)| UUID::parse_str(id).ok())
+}
+
+/// Get the category of a partition type
+///
+/// Returns `Unknown` if the type is not known
+pub fn category(uuid: &UUID) -> TypeCategory {
+    TYPES.iter()
+        .find(|&&(id, _, _)| UUID::parse_str(id).ok().as_ref() == Some(uuid))
+        .map_or(TypeCategory::Unknown, |&(_, _, category)| category)
+}
+
+/// MBR partition types together with the name of the matching partition type
+const MBR_TYPES: &[(u8, &str)] = &[
+    (0x07, "Microsoft basic data"),
+    (0x82, "Linux swap"),
+    (0x83, "Linux filesystem"),
+    (0x8E, "Linux LVM"),
+    (0xFD, "Linux RAID"),
+    (0xEF, "EFI System Partition"),
+    (0x27, "Windows recovery environment"),
+    (0xAF, "Apple HFS+"),
+    (0xA5, "FreeBSD data"),
+];
+
+/// Get the partition type corresponding to a MBR partition type
+///
+/// Unknown types are mapped to Microsoft basic data
+pub fn type_for_mbr_id(system_id: u8) -> UUID {
+    let name = MBR_TYPES.iter()
+        .find(|&&(id, _)| id == system_id)
+        .map_or("Microsoft basic data", |&(_, name)| name);
+    type_for_name(name).expect("MBR type mapping refers to unknown GPT type")
+}
+
+/// Get the MBR partition type corresponding to a partition type
+///
+/// Unknown types are mapped to 0x07, which is used for Microsoft basic data
+pub fn mbr_id_for_type(uuid: &UUID) -> u8 {
+    name_for_type(uuid)
+        .and_then(|name| MBR_TYPES.iter().find(|&&(_, n)| n == name))
+        .map_or(0x07, |&(id, _)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let esp = UUID::parse_str("c12a7328-f81f-11d2-ba4b-00a0c93ec93b").unwrap();
+        assert_eq!(name_for_type(&esp), Some("EFI System Partition"));
+        assert_eq!(type_for_name("EFI System Partition"), Some(esp));
+        assert_eq!(name_for_type(&UUID::nil()), None);
+        assert_eq!(type_for_name("No such type"), None);
+        // Every entry can be found by its name
+        for &(id, name, _) in TYPES {
+            assert_eq!(type_for_name(name), UUID::parse_str(id).ok());
+        }
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(*LINUX_SWAP, UUID::parse_str("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F").unwrap());
+        assert_eq!(name_for_type(&EFI_SYSTEM), Some("EFI System Partition"));
+        assert_eq!(category(&BIOS_BOOT), TypeCategory::BiosBoot);
+        assert_eq!(category(&LINUX_FILESYSTEM), TypeCategory::Linux);
+        assert_eq!(category(&LINUX_LVM), TypeCategory::Lvm);
+        assert_eq!(category(&MICROSOFT_BASIC_DATA), TypeCategory::Windows);
+        assert_eq!(category(&MICROSOFT_RESERVED), TypeCategory::Windows);
+    }
+
+    #[test]
+    fn categories() {
+        assert_eq!(category(&EFI_SYSTEM), TypeCategory::Efi);
+        // Swap is grouped by purpose, not by operating system
+        assert_eq!(category(&LINUX_SWAP), TypeCategory::Swap);
+        assert_eq!(category(&type_for_name("FreeBSD swap").unwrap()), TypeCategory::Swap);
+        assert_eq!(category(&type_for_name("Linux RAID").unwrap()), TypeCategory::Raid);
+        assert_eq!(category(&type_for_name("Apple APFS").unwrap()), TypeCategory::Apple);
+        assert_eq!(category(&type_for_name("FreeBSD ZFS").unwrap()), TypeCategory::Bsd);
+        assert_eq!(category(&type_for_name("MBR partition scheme").unwrap()), TypeCategory::Other);
+        assert_eq!(category(&UUID::nil()), TypeCategory::Unknown);
+    }
+}
@@ -1,7 +1,18 @@
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
 pub mod util;
 pub mod gpt;
 pub mod mbr;
 pub mod window;
+mod detect;
 
 pub use mbr::MBR;
 pub use gpt::GPTTable as GPT;
+pub use window::Window;
+pub use detect::{detect, DiskLayout};
@@ -1,13 +1,87 @@
 extern crate byteorder;
 
-use self::byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian, BigEndian};
-use std::io::{Result as IOResult, Read, Seek, SeekFrom, Write};
+use self::byteorder::{ReadBytesExt, WriteBytesExt, ByteOrder, LittleEndian};
+use std::io::{Result as IOResult, Read, Seek, SeekFrom, Write, Error as IOError, ErrorKind};
+use std::error::Error;
+use std::collections::HashSet;
 use std::{fmt, cmp};
+use util::Geometry;
+use window::Window;
+
+const SECTOR_SIZE: u64 = 512;
+/// The boot signature, stored as the bytes 0x55 0xAA
+const BOOT_SIGNATURE: u16 = 0xAA55;
+/// Offset of the boot signature within a MBR or EBR
+const SIGNATURE_OFFSET: u64 = 510;
+/// Offset of the partition table within a MBR or EBR
+const PARTITION_TABLE_OFFSET: u64 = 446;
+
+#[derive(Debug)]
+pub enum MBRError {
+    /// During parsing or writing a MBR an IO Error occured
+    IOError(IOError),
+    /// The boot signature is missing. Contains the signature that was found instead.
+    InvalidSignature(u16),
+    /// All four primary partition slots are in use
+    TableFull,
+    /// The partition entry is not valid, e.g. it has a system id of 0
+    InvalidPartition,
+    /// The id is not one of the four primary partition slots, or the slot is not in use where a
+    /// partition is required
+    InvalidID,
+    /// The partition overlaps with another partition
+    Overlap
+}
+
+impl From<IOError> for MBRError {
+    fn from(err: IOError) -> MBRError {
+        MBRError::IOError(err)
+    }
+}
+
+impl Error for MBRError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            MBRError::IOError(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for MBRError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MBRError::IOError(ref e) => write!(f, "IO Error while processing MBR: {}", e),
+            MBRError::InvalidSignature(sig) => write!(f, "Invalid MBR boot signature: {:#06x}", sig),
+            MBRError::TableFull => write!(f, "All primary partition slots are in use"),
+            MBRError::InvalidPartition => write!(f, "Invalid partition entry"),
+            MBRError::InvalidID => write!(f, "Invalid partition slot"),
+            MBRError::Overlap => write!(f, "Partition overlaps with another partition")
+        }
+    }
+}
+
+/// How to fill the CHS fields of partitions when writing a MBR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MbrWriteMode {
+    /// Keep the CHS fields of each partition, only zeroed fields are computed from the LBA
+    Chs,
+    /// Always compute the CHS fields from the LBA. Addresses beyond the 1024 cylinders reachable
+    /// by CHS are written as 0xFE 0xFF 0xFF, like fdisk does.
+    LbaOnly
+}
 
 /// A structure representing a Master Boot Record
+///
+/// Logical partitions within extended partitions are read-only: they are collected by `load`,
+/// but can not be modified and are not written by `write_mbr`, which leaves the extended boot
+/// records on disk untouched.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MBR {
+    #[cfg_attr(feature = "serde", serde(with = "bootloader_serde"))]
     bootloader: [u8; 446],
     partitions: [Option<PartitionEntry>; 4],
+    logical: Vec<PartitionEntry>,
     boot_sig: u16
 }
 
@@ -17,52 +91,254 @@ impl MBR {
         MBR::default()
     }
 
+    /// Initialize a protective MBR for a GPT disk of `disk_blocks` blocks
+    ///
+    /// The MBR contains a single partition of type 0xEE spanning from block 1 to the end of
+    /// the disk, clamped to the maximum size a MBR can describe.
+    pub fn protective(disk_blocks: u64) -> MBR {
+        let mut mbr = MBR::new();
+        mbr.partitions[0] = Some(PartitionEntry {
+            bootable: false,
+            system_id: 0xEE,
+            start_lba: 1,
+            sector_count: cmp::min(disk_blocks.saturating_sub(1), 0xFFFFFFFF) as u32,
+            start_chs: [0u8; 3],
+            end_chs: [0u8; 3]
+        });
+        mbr
+    }
+
     /// Load a MBR from stream
-    pub fn load<R: Read + Seek>(read: &mut R) -> IOResult<MBR> {
-        read.seek(SeekFrom::Start(0))?;
+    ///
+    /// Fails with `InvalidSignature` if the boot signature 0x55 0xAA is missing, unless
+    /// `allow_missing_signature` is set.
+    pub fn load<R: Read + Seek>(read: &mut R, allow_missing_signature: bool) -> Result<MBR, MBRError> {
+        // Parse the first sector through a window on it, so it can not read past it
+        let mut block = Window::new(read, 0, SECTOR_SIZE);
         let mut stage0 = [0u8; 446];
-        read.read(&mut stage0)?;
+        block.read_exact(&mut stage0)?;
         let mut parts = [None; 4];
         for i in 0..4 {
-            parts[i] = PartitionEntry::load(read)?;
+            parts[i] = PartitionEntry::load(&mut block)?;
+        }
+        // Don't rely on the partition entries leaving the stream at the right position
+        block.seek(SeekFrom::Start(SIGNATURE_OFFSET))?;
+        let sig = block.read_u16::<LittleEndian>()?;
+        if sig != BOOT_SIGNATURE && !allow_missing_signature {
+            return Err(MBRError::InvalidSignature(sig));
+        }
+
+        let mut logical = Vec::new();
+        for p in parts.iter().filter_map(|p| p.as_ref()).filter(|p| is_extended(p.system_id)) {
+            logical.extend(load_logical(read, p)?);
         }
-        let sig = read.read_u16::<LittleEndian>()?;
 
         Ok(MBR {
             bootloader: stage0,
             partitions: parts,
+            logical,
             boot_sig: sig
         })
     }
 
-    /// Load a GPT from stream
-    pub fn write_mbr<W: Write + Seek>(&self, write: &mut W) -> IOResult<()> {
+    /// Write the MBR to a stream
+    ///
+    /// Only block 0 is written, so the logical partitions in extended partitions are kept as
+    /// they are on disk.
+    pub fn write_mbr<W: Write + Seek>(&self, write: &mut W, mode: MbrWriteMode) -> Result<(), MBRError> {
+        self.write_mbr_with_geometry(write, mode, &Geometry::default())
+    }
+
+    /// Write the MBR, computing CHS addresses for the given disk geometry instead of the
+    /// default of 255 heads and 63 sectors per track
+    pub fn write_mbr_with_geometry<W: Write + Seek>(&self, write: &mut W, mode: MbrWriteMode, geometry: &Geometry) -> Result<(), MBRError> {
         write.seek(SeekFrom::Start(0))?;
-        write.write(&self.bootloader)?;
+        write.write_all(&self.bootloader)?;
+        self.write_entries(write, mode, geometry)
+    }
+
+    /// Write only the partition table and the boot signature, keeping the boot code and the
+    /// disk signature already on disk
+    pub fn write_partition_table<W: Write + Seek>(&self, write: &mut W, mode: MbrWriteMode) -> Result<(), MBRError> {
+        write.seek(SeekFrom::Start(PARTITION_TABLE_OFFSET))?;
+        self.write_entries(write, mode, &Geometry::default())
+    }
+
+    /// Write the partition entries at the current position, followed by the boot signature
+    fn write_entries<W: Write + Seek>(&self, write: &mut W, mode: MbrWriteMode, geometry: &Geometry) -> Result<(), MBRError> {
         for p in &self.partitions {
-            match p {
-                &Some(ref part) => part.write(write)?,
-                &None => PartitionEntry::default().write(write)?
+            match *p {
+                Some(ref part) => part.write(write, mode, geometry)?,
+                None => write.write_all(&[0u8; 16])?
             }
         }
-        write.write_u16::<BigEndian>(0x55AA)?;
+        write.seek(SeekFrom::Start(SIGNATURE_OFFSET))?;
+        write.write_u16::<LittleEndian>(self.boot_sig)?;
         Ok(())
     }
 
+    /// Gets the boot signature, read as a little endian number. 0xAA55 if the signature is valid.
+    pub fn boot_signature(&self) -> u16 {
+        self.boot_sig
+    }
+
+    /// Sets the boot signature, written as a little endian number
+    pub fn set_boot_signature(&mut self, sig: u16) {
+        self.boot_sig = sig;
+    }
+
     /// Get a list of all primary partitions
     pub fn partitions(&self) -> &[Option<PartitionEntry>] {
         &self.partitions
     }
 
-    /// Count all primary partitions. May be changed in the future to also include logical
-    /// partitions, once they are supported by this library
-    pub fn partition_count(&self) -> u8 {
-        self.partitions.iter().filter(|p| p.is_some()).count() as u8
+    /// Get a list of all logical partitions within extended partitions
+    ///
+    /// Logical partitions are read-only, see `MBR`.
+    pub fn logical_partitions(&self) -> &[PartitionEntry] {
+        &self.logical
+    }
+
+    /// Iterate over all used primary partition slots, together with their slot id
+    pub fn used_partitions(&self) -> impl Iterator<Item = (usize, &PartitionEntry)> {
+        self.partitions.iter().enumerate().filter_map(|(i, p)| p.as_ref().map(|p| (i, p)))
+    }
+
+    /// Add a primary partition in the first free slot
+    ///
+    /// Returns the id of the slot the partition was placed in. Fails if the system id is 0, as
+    /// that marks an empty slot.
+    pub fn add_partition(&mut self, entry: PartitionEntry) -> Result<usize, MBRError> {
+        if entry.system_id == 0 {
+            return Err(MBRError::InvalidPartition);
+        }
+        match self.partitions.iter().position(|p| p.is_none()) {
+            Some(id) => {
+                self.partitions[id] = Some(entry);
+                Ok(id)
+            },
+            None => Err(MBRError::TableFull)
+        }
+    }
+
+    /// Mark a primary partition as bootable and clear the flag on all others
+    ///
+    /// BIOS boots the first partition marked as bootable, so there should only be one. Fails
+    /// with `InvalidID` if the slot is not in use.
+    pub fn set_bootable(&mut self, id: usize) -> Result<(), MBRError> {
+        match self.partitions.get(id) {
+            Some(&Some(_)) => {},
+            _ => return Err(MBRError::InvalidID)
+        }
+        for (i, p) in self.partitions.iter_mut().enumerate() {
+            if let Some(ref mut p) = *p {
+                p.bootable = i == id;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the slot of the first primary partition marked as bootable
+    pub fn bootable_partition(&self) -> Option<usize> {
+        self.used_partitions().find(|&(_, p)| p.bootable).map(|(i, _)| i)
+    }
+
+    /// Add a primary partition in the first free slot, but only if it does not overlap any
+    /// other primary partition
+    ///
+    /// Returns the id of the slot the partition was placed in.
+    pub fn add_partition_checked(&mut self, entry: PartitionEntry) -> Result<usize, MBRError> {
+        if self.used_partitions().any(|(_, p)| p.overlaps(&entry)) {
+            return Err(MBRError::Overlap);
+        }
+        self.add_partition(entry)
+    }
+
+    /// Place a primary partition in slot `id`, replacing whatever is there
+    ///
+    /// Fails with `InvalidID` if `id` is not in 0..4, or with `InvalidPartition` if the system
+    /// id is 0.
+    pub fn set_partition(&mut self, id: usize, entry: PartitionEntry) -> Result<(), MBRError> {
+        if id >= self.partitions.len() {
+            return Err(MBRError::InvalidID);
+        }
+        if entry.system_id == 0 {
+            return Err(MBRError::InvalidPartition);
+        }
+        self.partitions[id] = Some(entry);
+        Ok(())
+    }
+
+    /// Place a primary partition in slot `id`, but only if it does not overlap any other
+    /// primary partition. The partition currently in slot `id` is ignored for the check.
+    pub fn set_partition_checked(&mut self, id: usize, entry: PartitionEntry) -> Result<(), MBRError> {
+        if self.used_partitions().any(|(i, p)| i != id && p.overlaps(&entry)) {
+            return Err(MBRError::Overlap);
+        }
+        self.set_partition(id, entry)
+    }
+
+    /// Mark a primary partition slot as empty
+    pub fn delete_partition(&mut self, id: usize) -> Result<(), MBRError> {
+        if id >= self.partitions.len() {
+            return Err(MBRError::InvalidID);
+        }
+        self.partitions[id] = None;
+        Ok(())
+    }
+
+    /// Find all pairs of primary partitions whose LBA ranges intersect
+    ///
+    /// Returns pairs of slot ids, with the lower id first.
+    pub fn find_overlaps(&self) -> Vec<(usize, usize)> {
+        let mut ret = Vec::new();
+        for (i, a) in self.used_partitions() {
+            for (j, b) in self.used_partitions().filter(|&(j, _)| j > i) {
+                if a.overlaps(b) {
+                    ret.push((i, j));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Gets the Windows NT disk signature stored at offset 440
+    pub fn disk_signature(&self) -> u32 {
+        LittleEndian::read_u32(&self.bootloader[440..444])
+    }
+
+    /// Sets the Windows NT disk signature stored at offset 440
+    pub fn set_disk_signature(&mut self, sig: u32) {
+        LittleEndian::write_u32(&mut self.bootloader[440..444], sig);
+    }
+
+    /// Checks whether this is a protective MBR of a GPT disk, consisting of a single partition
+    /// of type 0xEE starting at block 1
+    pub fn is_protective(&self) -> bool {
+        let mut parts = self.partitions.iter().filter_map(|p| p.as_ref());
+        match (parts.next(), parts.next()) {
+            (Some(p), None) => p.system_id == 0xEE && p.start_lba == 1,
+            _ => false
+        }
+    }
+
+    /// Checks whether this is a hybrid MBR of a GPT disk, where a partition of type 0xEE
+    /// coexists with other partitions
+    pub fn is_hybrid(&self) -> bool {
+        let parts = self.partitions.iter().filter_map(|p| p.as_ref());
+        let gpt = parts.clone().filter(|p| p.system_id == 0xEE).count();
+        gpt > 0 && parts.count() > gpt
+    }
+
+    /// Count all primary and logical partitions. Does include the host partition for logical
+    /// partitions
+    pub fn partition_count(&self) -> usize {
+        self.primary_partition_count() as usize + self.logical.len()
     }
 
     /// Count all primary partitions. Does include the host partition for logical partitions
     pub fn primary_partition_count(&self) -> u8 {
-        self.partition_count()
+        self.partitions.iter().filter(|p| p.is_some()).count() as u8
     }
 
 }
@@ -72,19 +348,21 @@ impl Default for MBR {
         MBR {
             bootloader: [0u8; 446],
             partitions: [None; 4],
-            boot_sig: 0
+            logical: Vec::new(),
+            boot_sig: BOOT_SIGNATURE
         }
     }
 }
 
 impl fmt::Debug for MBR {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        fmt.debug_struct("MBR").field("bootloader", &"[446 Bytes]").field("partitions", &self.partitions).field("boot_sig", &self.boot_sig).finish()?;
+        fmt.debug_struct("MBR").field("bootloader", &"[446 Bytes]").field("partitions", &self.partitions).field("logical", &self.logical).field("boot_sig", &self.boot_sig).finish()?;
         Ok(())
     }
 }
 
 #[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PartitionEntry {
     /// Is this partition marked as bootable
     pub bootable: bool,
@@ -94,14 +372,63 @@ pub struct PartitionEntry {
     pub start_lba: u32,
     /// How long the partition is in blocks
     pub sector_count: u32,
+    /// The raw CHS address of the first sector. If zeroed, it is computed from `start_lba`
+    /// when writing.
+    pub start_chs: [u8; 3],
+    /// The raw CHS address of the last sector. If zeroed, it is computed from `start_lba` and
+    /// `sector_count` when writing.
+    pub end_chs: [u8; 3],
+}
+
+impl fmt::Display for PartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match type_name(self.system_id) {
+            Some(name) => write!(f, "{} ({:#04x})", name, self.system_id)?,
+            None => write!(f, "{:#04x}", self.system_id)?
+        }
+        if self.bootable {
+            write!(f, ", bootable")?;
+        }
+        write!(f, ", start LBA {}, {} sectors", self.start_lba, self.sector_count)
+    }
 }
 
 impl PartitionEntry {
+    /// Gets the size of the partition in bytes
+    pub fn size_bytes(&self, block_size: u16) -> u64 {
+        self.sector_count as u64 * block_size as u64
+    }
+
+    /// Gets the last LBA of the partition
+    ///
+    /// For a partition without sectors this is `start_lba`. Saturates at the end of the
+    /// addressable range.
+    pub fn end_lba(&self) -> u32 {
+        self.start_lba.saturating_add(self.sector_count.saturating_sub(1))
+    }
+
+    /// Checks whether the LBA ranges of two partitions intersect. Partitions without sectors
+    /// never overlap.
+    pub fn overlaps(&self, other: &PartitionEntry) -> bool {
+        // Computed in 64 bit, a partition may end beyond the 32 bit LBA range
+        let end = self.start_lba as u64 + self.sector_count as u64;
+        let other_end = other.start_lba as u64 + other.sector_count as u64;
+        self.sector_count != 0 && other.sector_count != 0
+            && (self.start_lba as u64) < other_end && (other.start_lba as u64) < end
+    }
+
+    /// Checks whether `lba` lies within the partition. Always false if it has no sectors.
+    pub fn contains_lba(&self, lba: u32) -> bool {
+        self.sector_count != 0 && lba >= self.start_lba && lba <= self.end_lba()
+    }
+
     fn load<R: Read + Seek>(read: &mut R) -> IOResult<Option<PartitionEntry>> {
         let boot = read.read_u8()? == 0x80;
-        read.seek(SeekFrom::Current(3))?; // Skip CHS
+        let mut start_chs = [0u8; 3];
+        read.read_exact(&mut start_chs)?;
         let system_id = read.read_u8()?;
-        read.seek(SeekFrom::Current(3))?; // Skip CHS
+        let mut end_chs = [0u8; 3];
+        read.read_exact(&mut end_chs)?;
         let start_lba = read.read_u32::<LittleEndian>()?;
         let sector_count = read.read_u32::<LittleEndian>()?;
 
@@ -110,14 +437,16 @@ impl PartitionEntry {
                 bootable: boot,
                 system_id,
                 start_lba,
-                sector_count
+                sector_count,
+                start_chs,
+                end_chs
             }))
         } else {
             Ok(None)
         }
     }
     
-    fn write<W: Write + Seek>(&self, write: &mut W) -> IOResult<()> {
+    fn write<W: Write + Seek>(&self, write: &mut W, mode: MbrWriteMode, geometry: &Geometry) -> IOResult<()> {
 
         if self.bootable {
             write.write_u8(0x80)?;
@@ -125,13 +454,18 @@ impl PartitionEntry {
             write.write_u8(0x00)?;
         }
 
-        let mut chs = [0u8; 3];
-        offset_to_chs(self.start_lba, &mut chs);
-        write.write(&chs)?;
+        let mut chs = self.start_chs;
+        if chs == [0u8; 3] || mode == MbrWriteMode::LbaOnly {
+            offset_to_chs(self.start_lba, geometry, &mut chs);
+        }
+        write.write_all(&chs)?;
         write.write_u8(self.system_id)?;
 
-        offset_to_chs(self.sector_count + self.start_lba - 1, &mut chs);
-        write.write(&chs)?;
+        let mut chs = self.end_chs;
+        if chs == [0u8; 3] || mode == MbrWriteMode::LbaOnly {
+            offset_to_chs(self.end_lba(), geometry, &mut chs);
+        }
+        write.write_all(&chs)?;
 
         write.write_u32::<LittleEndian>(self.start_lba)?;
         write.write_u32::<LittleEndian>(self.sector_count)?;
@@ -143,17 +477,591 @@ impl PartitionEntry {
 
 }
 
-fn offset_to_chs(offset: u32, buf: &mut [u8]) {
+/// Builder for MBR partition entries
+///
+/// The CHS addresses are left zeroed, so they are computed from the LBA when writing.
+pub struct PartitionEntryBuilder {
+    bootable: bool,
+    system_id: u8,
+    start_lba: u32,
+    sector_count: u32
+}
+
+impl PartitionEntryBuilder {
+    /// Creates a new builder for an empty, not bootable partition
+    pub fn new() -> PartitionEntryBuilder {
+        PartitionEntryBuilder {
+            bootable: false,
+            system_id: 0,
+            start_lba: 0,
+            sector_count: 0
+        }
+    }
+
+    /// Sets whether the partition is marked as bootable
+    pub fn bootable(mut self, bootable: bool) -> PartitionEntryBuilder {
+        self.bootable = bootable;
+        self
+    }
+
+    /// Sets the partition type
+    pub fn system_id(mut self, system_id: u8) -> PartitionEntryBuilder {
+        self.system_id = system_id;
+        self
+    }
+
+    /// Sets the LBA at which the partition starts
+    pub fn start_lba(mut self, start_lba: u32) -> PartitionEntryBuilder {
+        self.start_lba = start_lba;
+        self
+    }
+
+    /// Sets the length of the partition in blocks
+    pub fn sectors(mut self, sector_count: u32) -> PartitionEntryBuilder {
+        self.sector_count = sector_count;
+        self
+    }
+
+    /// Builds the partition entry
+    ///
+    /// Fails with `InvalidPartition` if the system id is 0, as that marks an empty slot, or if
+    /// the partition has no sectors
+    pub fn build(self) -> Result<PartitionEntry, MBRError> {
+        if self.system_id == 0 || self.sector_count == 0 {
+            return Err(MBRError::InvalidPartition);
+        }
+        Ok(PartitionEntry {
+            bootable: self.bootable,
+            system_id: self.system_id,
+            start_lba: self.start_lba,
+            sector_count: self.sector_count,
+            start_chs: [0u8; 3],
+            end_chs: [0u8; 3]
+        })
+    }
+}
 
-    let c = offset % 1024;
-    let offset = offset / 1024;
+impl Default for PartitionEntryBuilder {
+    fn default() -> PartitionEntryBuilder {
+        PartitionEntryBuilder::new()
+    }
+}
 
-    let h = offset % 256;
-    let offset = offset / 256;
+fn is_extended(system_id: u8) -> bool {
+    matches!(system_id, 0x05 | 0x0F | 0x85)
+}
 
-    let s = cmp::max(offset, 63);
+/// Follow the chain of extended boot records within an extended partition and collect the
+/// logical partitions. The start of the logical partitions is converted to an absolute LBA.
+fn load_logical<R: Read + Seek>(read: &mut R, extended: &PartitionEntry) -> IOResult<Vec<PartitionEntry>> {
+    let mut ret = Vec::new();
+    let mut visited = HashSet::new();
+    let mut ebr = extended.start_lba;
+
+    // Stop on loops in the chain
+    while visited.insert(ebr) {
+        let (logical, next) = match load_ebr(read, ebr) {
+            Ok(Some(entries)) => entries,
+            Ok(None) => break,
+            // The EBR lies beyond the end of the stream, e.g. in a dump of only the MBR
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e)
+        };
+
+        if let Some(mut p) = logical {
+            // The start of a logical partition is relative to its EBR
+            p.start_lba = p.start_lba.saturating_add(ebr);
+            ret.push(p);
+        }
+
+        match next {
+            // The next EBR is relative to the start of the extended partition
+            Some(next) => ebr = next.start_lba.saturating_add(extended.start_lba),
+            None => break
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Read the logical partition and the link to the next EBR from the EBR at `ebr`
+///
+/// Returns None if the EBR has no boot signature.
+fn load_ebr<R: Read + Seek>(read: &mut R, ebr: u32) -> IOResult<Option<(Option<PartitionEntry>, Option<PartitionEntry>)>> {
+    let mut block = Window::new(read, ebr as u64 * SECTOR_SIZE, SECTOR_SIZE);
+    block.seek(SeekFrom::Start(SIGNATURE_OFFSET))?;
+    if block.read_u16::<LittleEndian>()? != BOOT_SIGNATURE {
+        return Ok(None);
+    }
+
+    block.seek(SeekFrom::Start(PARTITION_TABLE_OFFSET))?;
+    let logical = PartitionEntry::load(&mut block)?;
+    let next = PartitionEntry::load(&mut block)?;
+    Ok(Some((logical, next)))
+}
+
+/// Get the human readable name of a partition type
+///
+/// Returns None if the type is not known
+pub fn type_name(system_id: u8) -> Option<&'static str> {
+    match system_id {
+        0x01 => Some("FAT12"),
+        0x04 => Some("FAT16 <32M"),
+        0x05 => Some("Extended"),
+        0x06 => Some("FAT16"),
+        0x07 => Some("NTFS/exFAT"),
+        0x0B => Some("FAT32"),
+        0x0C => Some("FAT32 (LBA)"),
+        0x0E => Some("FAT16 (LBA)"),
+        0x0F => Some("Extended (LBA)"),
+        0x27 => Some("Windows recovery environment"),
+        0x82 => Some("Linux swap"),
+        0x83 => Some("Linux"),
+        0x85 => Some("Linux extended"),
+        0x8E => Some("Linux LVM"),
+        0xA5 => Some("FreeBSD"),
+        0xA6 => Some("OpenBSD"),
+        0xA9 => Some("NetBSD"),
+        0xAF => Some("Apple HFS/HFS+"),
+        0xEE => Some("GPT protective MBR"),
+        0xEF => Some("EFI System Partition"),
+        0xFD => Some("Linux RAID"),
+        _ => None
+    }
+}
+
+/// Convert a LBA to a CHS tuple for the given geometry
+///
+/// Addresses beyond cylinder 1023 can not be represented and are written as 0xFE 0xFF 0xFF,
+/// as are all addresses of a geometry which can not be addressed with CHS, i.e. without heads
+/// or sectors, or with more than 255 heads or 63 sectors per track.
+fn offset_to_chs(offset: u32, geometry: &Geometry, buf: &mut [u8]) {
+    let heads = geometry.heads as u32;
+    let sectors = geometry.sectors as u32;
+
+    if heads == 0 || heads > 255 || sectors == 0 || sectors > 63 || offset / (sectors * heads) > 1023 {
+        buf[0] = 0xFE;
+        buf[1] = 0xFF;
+        buf[2] = 0xFF;
+        return;
+    }
+
+    let c = offset / (sectors * heads);
+    let h = (offset / sectors) % heads;
+    let s = (offset % sectors) + 1;
 
     buf[0] = h as u8;
     buf[1] = (s | ((c & 0x0300) >> 2)) as u8;
     buf[2] = (c & 0xFF) as u8;
 }
+
+/// Serde does not support arrays of this size, so the bootloader is serialized as bytes
+#[cfg(feature = "serde")]
+mod bootloader_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(bootloader: &[u8; 446], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bootloader)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 446], D::Error> {
+        let buf = Vec::<u8>::deserialize(deserializer)?;
+        if buf.len() != 446 {
+            return Err(D::Error::invalid_length(buf.len(), &"446 bytes"));
+        }
+        let mut bootloader = [0u8; 446];
+        bootloader.copy_from_slice(&buf);
+        Ok(bootloader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use util::{ShortWriter, blank_image};
+
+    fn chs(offset: u32) -> [u8; 3] {
+        let mut buf = [0u8; 3];
+        offset_to_chs(offset, &Geometry::default(), &mut buf);
+        buf
+    }
+
+    #[test]
+    fn chs_addresses() {
+        assert_eq!(chs(0), [0, 1, 0]);
+        assert_eq!(chs(1), [0, 2, 0]);
+        assert_eq!(chs(63), [1, 1, 0]);
+        assert_eq!(chs(2048), [32, 33, 0]);
+        assert_eq!(chs(16065), [0, 1, 1]);
+        // Bits 8 and 9 of the cylinder are stored in the sector byte
+        assert_eq!(chs(63 * 255 * 256), [0, 0x41, 0]);
+        assert_eq!(chs(63 * 255 * 1024 - 1), [254, 0xFF, 0xFF]);
+        assert_eq!(chs(63 * 255 * 1024), [0xFE, 0xFF, 0xFF]);
+    }
+
+    fn part(start_lba: u32, sectors: u32) -> PartitionEntry {
+        PartitionEntryBuilder::new().system_id(0x83).start_lba(start_lba).sectors(sectors).build().unwrap()
+    }
+
+    #[test]
+    fn add_partition_fills_free_slots() {
+        let mut mbr = MBR::new();
+        for i in 0..4 {
+            assert_eq!(mbr.add_partition(part(1 + i as u32, 1)).unwrap(), i);
+        }
+        assert!(matches!(mbr.add_partition(part(10, 1)), Err(MBRError::TableFull)));
+
+        mbr.delete_partition(1).unwrap();
+        assert_eq!(mbr.add_partition(part(10, 1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn protective_and_hybrid() {
+        let mbr = MBR::protective(1000);
+        assert!(mbr.is_protective());
+        assert!(!mbr.is_hybrid());
+
+        let mut hybrid = MBR::protective(1000);
+        hybrid.add_partition(part(2048, 10)).unwrap();
+        assert!(!hybrid.is_protective());
+        assert!(hybrid.is_hybrid());
+
+        let mut plain = MBR::new();
+        plain.add_partition(part(2048, 10)).unwrap();
+        assert!(!plain.is_protective());
+        assert!(!plain.is_hybrid());
+    }
+
+    #[test]
+    fn disk_signature() {
+        let mut mbr = MBR::new();
+        mbr.set_disk_signature(0xDEADBEEF);
+        let mut image = blank_image(1, 512);
+        mbr.write_mbr(&mut image, MbrWriteMode::Chs).unwrap();
+        assert_eq!(&image.get_ref()[440..444], &[0xEF, 0xBE, 0xAD, 0xDE]);
+        assert_eq!(MBR::load(&mut image, false).unwrap().disk_signature(), 0xDEADBEEF);
+    }
+
+    #[test]
+    fn type_names() {
+        assert_eq!(type_name(0x83), Some("Linux"));
+        assert_eq!(type_name(0x07), Some("NTFS/exFAT"));
+        assert_eq!(type_name(0xEE), Some("GPT protective MBR"));
+        assert_eq!(type_name(0x82), Some("Linux swap"));
+        assert_eq!(type_name(0x00), None);
+    }
+
+    #[test]
+    fn display() {
+        let mut p = PartitionEntryBuilder::new().system_id(0x83).bootable(true).start_lba(2048).sectors(100).build().unwrap();
+        assert_eq!(p.to_string(), "Linux (0x83), bootable, start LBA 2048, 100 sectors");
+        p.system_id = 0x42;
+        p.bootable = false;
+        assert_eq!(p.to_string(), "0x42, start LBA 2048, 100 sectors");
+    }
+
+    #[test]
+    fn size_bytes() {
+        assert_eq!(part(2048, 100).size_bytes(512), 100 * 512);
+        // Does not overflow for the largest possible partition
+        assert_eq!(part(2048, 0xFFFF_FFFF).size_bytes(4096), 0xFFFF_FFFF * 4096);
+    }
+
+    #[test]
+    fn chs_fields_round_trip() {
+        let mut mbr = MBR::new();
+        let mut kept = part(2048, 100);
+        kept.start_chs = [1, 2, 3];
+        kept.end_chs = [4, 5, 6];
+        mbr.add_partition(kept).unwrap();
+        mbr.add_partition(part(4096, 100)).unwrap();
+
+        let mut image = blank_image(1, 512);
+        mbr.write_mbr(&mut image, MbrWriteMode::Chs).unwrap();
+        assert_eq!(&image.get_ref()[447..450], &[1, 2, 3]);
+        assert_eq!(&image.get_ref()[451..454], &[4, 5, 6]);
+        // Zeroed fields are computed
+        assert_eq!(&image.get_ref()[463..466], &[65, 2, 0]);
+
+        let loaded = MBR::load(&mut image, false).unwrap();
+        assert_eq!(loaded.partitions()[0].unwrap().start_chs, [1, 2, 3]);
+        let mut written = blank_image(1, 512);
+        loaded.write_mbr(&mut written, MbrWriteMode::Chs).unwrap();
+        assert_eq!(written.get_ref(), image.get_ref());
+    }
+
+    /// Write a partition entry with only type and location into a raw image
+    fn raw_entry(buf: &mut [u8], offset: usize, system_id: u8, start_lba: u32, sectors: u32) {
+        buf[offset + 4] = system_id;
+        LittleEndian::write_u32(&mut buf[offset + 8..offset + 12], start_lba);
+        LittleEndian::write_u32(&mut buf[offset + 12..offset + 16], sectors);
+        LittleEndian::write_u16(&mut buf[offset - 446 + 510..offset - 446 + 512], BOOT_SIGNATURE);
+    }
+
+    #[test]
+    fn logical_partitions() {
+        let mut image = blank_image(2000, 512);
+        {
+            let buf = image.get_mut();
+            raw_entry(buf, 446, 0x83, 1, 50);
+            raw_entry(buf, 462, 0x05, 100, 1000);
+            // Logical partitions are relative to their EBR, links to the extended partition
+            raw_entry(buf, 100 * 512 + 446, 0x83, 2, 10);
+            raw_entry(buf, 100 * 512 + 462, 0x05, 50, 20);
+            raw_entry(buf, 150 * 512 + 446, 0x82, 2, 5);
+            // Loops back to the first EBR
+            raw_entry(buf, 150 * 512 + 462, 0x05, 0, 20);
+        }
+
+        let mbr = MBR::load(&mut image, false).unwrap();
+        let logical = mbr.logical_partitions();
+        assert_eq!(logical.len(), 2);
+        assert_eq!((logical[0].system_id, logical[0].start_lba), (0x83, 102));
+        assert_eq!((logical[1].system_id, logical[1].start_lba), (0x82, 152));
+        assert_eq!(mbr.partition_count(), 4);
+        assert_eq!(mbr.primary_partition_count(), 2);
+    }
+
+    #[test]
+    fn logical_partitions_beyond_stream() {
+        // A dump of only the MBR of a disk with an extended partition
+        let mut mbr = MBR::new();
+        mbr.add_partition(part(2048, 100)).unwrap();
+        mbr.add_partition(PartitionEntryBuilder::new().system_id(0x05).start_lba(4096).sectors(100).build().unwrap()).unwrap();
+        let mut image = blank_image(1, 512);
+        mbr.write_mbr(&mut image, MbrWriteMode::Chs).unwrap();
+
+        let loaded = MBR::load(&mut image, false).unwrap();
+        assert_eq!(loaded.primary_partition_count(), 2);
+        assert!(loaded.logical_partitions().is_empty());
+    }
+
+    #[test]
+    fn long_ebr_chain() {
+        let mut image = blank_image(1000, 512);
+        {
+            let buf = image.get_mut();
+            raw_entry(buf, 446, 0x05, 100, 800);
+            // More logical partitions than fit into a u8
+            for i in 0..300 {
+                let ebr = (100 + 2 * i) * 512;
+                raw_entry(buf, ebr + 446, 0x83, 1, 1);
+                if i < 299 {
+                    raw_entry(buf, ebr + 462, 0x05, 2 * (i as u32 + 1), 2);
+                }
+            }
+        }
+
+        let mbr = MBR::load(&mut image, false).unwrap();
+        assert_eq!(mbr.logical_partitions().len(), 300);
+        assert_eq!(mbr.partition_count(), 301);
+    }
+
+    #[test]
+    fn lba_only_mode() {
+        let mut mbr = MBR::new();
+        let mut far = part(20_000_000, 1000);
+        far.start_chs = [1, 2, 3];
+        far.end_chs = [4, 5, 6];
+        mbr.add_partition(far).unwrap();
+        let mut near = part(2048, 1000);
+        near.start_chs = [9, 9, 9];
+        mbr.add_partition(near).unwrap();
+
+        let mut image = blank_image(1, 512);
+        mbr.write_mbr(&mut image, MbrWriteMode::LbaOnly).unwrap();
+        // Beyond 1024 cylinders, so the CHS fields are maxed out
+        assert_eq!(&image.get_ref()[447..450], &[0xFE, 0xFF, 0xFF]);
+        assert_eq!(&image.get_ref()[451..454], &[0xFE, 0xFF, 0xFF]);
+        // Stored CHS values are replaced by ones computed from the LBA
+        assert_eq!(&image.get_ref()[463..466], &[32, 33, 0]);
+
+        let mut image = blank_image(1, 512);
+        mbr.write_mbr(&mut image, MbrWriteMode::Chs).unwrap();
+        assert_eq!(&image.get_ref()[447..450], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn typed_errors() {
+        let mut image = blank_image(1, 512);
+        assert!(matches!(MBR::load(&mut image, false), Err(MBRError::InvalidSignature(0))));
+        assert!(MBR::load(&mut image, true).is_ok());
+        image.get_mut().truncate(100);
+        assert!(matches!(MBR::load(&mut image, true), Err(MBRError::IOError(_))));
+        assert!(matches!(MBR::new().add_partition(PartitionEntry::default()), Err(MBRError::InvalidPartition)));
+    }
+
+    #[test]
+    fn boot_signature_byte_order() {
+        let mut image = blank_image(1, 512);
+        MBR::new().write_mbr(&mut image, MbrWriteMode::Chs).unwrap();
+        assert_eq!(&image.get_ref()[510..512], &[0x55, 0xAA]);
+
+        let loaded = MBR::load(&mut image, false).unwrap();
+        let mut written = blank_image(1, 512);
+        loaded.write_mbr(&mut written, MbrWriteMode::Chs).unwrap();
+        assert_eq!(written.get_ref(), image.get_ref());
+    }
+
+    #[test]
+    fn keeps_boot_signature() {
+        assert_eq!(MBR::new().boot_signature(), BOOT_SIGNATURE);
+
+        // Loaded without checking, so the invalid signature is written back as is
+        let mbr = MBR::load(&mut blank_image(1, 512), true).unwrap();
+        assert_eq!(mbr.boot_signature(), 0);
+        let mut image = Cursor::new(vec![0xFFu8; 512]);
+        mbr.write_mbr(&mut image, MbrWriteMode::Chs).unwrap();
+        assert_eq!(&image.get_ref()[510..512], &[0, 0]);
+    }
+
+    #[test]
+    fn full_boot_signature() {
+        let mut image = blank_image(2, 512);
+        {
+            let buf = image.get_mut();
+            raw_entry(buf, 446, 0x83, 1, 10);
+            // Only the last two bytes of the sector are the signature
+            for b in &mut buf[462..510] {
+                *b = 0xFF;
+            }
+            buf[510] = 0x34;
+            buf[511] = 0x12;
+        }
+        assert!(matches!(MBR::load(&mut image, false), Err(MBRError::InvalidSignature(0x1234))));
+        let mbr = MBR::load(&mut image, true).unwrap();
+        assert_eq!(mbr.boot_signature(), 0x1234);
+
+        let mut written = blank_image(2, 512);
+        mbr.write_mbr(&mut written, MbrWriteMode::Chs).unwrap();
+        assert_eq!(&written.get_ref()[510..512], &[0x34, 0x12]);
+    }
+
+    #[test]
+    fn builder_validation() {
+        assert!(matches!(PartitionEntryBuilder::new().start_lba(2048).sectors(10).build(), Err(MBRError::InvalidPartition)));
+        assert!(PartitionEntryBuilder::new().system_id(0x83).start_lba(2048).build().is_err());
+
+        let p = PartitionEntryBuilder::new().system_id(0x83).bootable(true).start_lba(2048).sectors(10).build().unwrap();
+        assert!(p.bootable);
+        assert_eq!((p.system_id, p.start_lba, p.sector_count), (0x83, 2048, 10));
+    }
+
+    #[test]
+    fn bootable_flag() {
+        let mut mbr = MBR::new();
+        for i in 0..4 {
+            let mut p = part(100 * (i + 1), 10);
+            p.bootable = i == 0;
+            mbr.add_partition(p).unwrap();
+        }
+        assert_eq!(mbr.bootable_partition(), Some(0));
+
+        // Only one partition is bootable at a time
+        mbr.set_bootable(2).unwrap();
+        assert_eq!(mbr.bootable_partition(), Some(2));
+        for &i in &[0, 1, 3] {
+            assert!(!mbr.partitions()[i].unwrap().bootable);
+        }
+        assert!(matches!(mbr.set_bootable(4), Err(MBRError::InvalidID)));
+        assert!(matches!(MBR::new().set_bootable(0), Err(MBRError::InvalidID)));
+    }
+
+    #[test]
+    fn end_lba() {
+        let p = part(100, 1);
+        assert_eq!(p.end_lba(), 100);
+        assert!(p.contains_lba(100));
+        assert!(!p.contains_lba(99));
+        assert!(!p.contains_lba(101));
+        assert_eq!(part(10, 20).end_lba(), 29);
+        // Saturates instead of wrapping around
+        assert_eq!(part(u32::MAX - 1, 10).end_lba(), u32::MAX);
+
+        let mut empty = part(100, 1);
+        empty.sector_count = 0;
+        assert_eq!(empty.end_lba(), 100);
+        assert!(!empty.contains_lba(100));
+    }
+
+    #[test]
+    fn overlaps() {
+        let mut mbr = MBR::new();
+        mbr.add_partition(part(100, 100)).unwrap();
+        mbr.add_partition(part(200, 50)).unwrap();
+        assert!(mbr.find_overlaps().is_empty());
+        mbr.add_partition(part(249, 1)).unwrap();
+        assert_eq!(mbr.find_overlaps(), vec![(1, 2)]);
+        assert!(matches!(mbr.add_partition_checked(part(150, 10)), Err(MBRError::Overlap)));
+
+        // Partitions reaching past the end of the LBA range do not wrap around
+        let mut mbr = MBR::new();
+        mbr.add_partition(part(u32::MAX - 10, 100)).unwrap();
+        mbr.add_partition_checked(part(10, 100)).unwrap();
+        assert!(mbr.find_overlaps().is_empty());
+        assert!(matches!(mbr.add_partition_checked(part(u32::MAX, 1)), Err(MBRError::Overlap)));
+        assert!(!part(u32::MAX - 10, 11).overlaps(&part(0, 10)));
+
+        let mut empty = part(5, 1);
+        empty.sector_count = 0;
+        assert!(!empty.overlaps(&part(0, 10)));
+    }
+
+    #[test]
+    fn set_and_delete_partition() {
+        let mut mbr = MBR::new();
+        mbr.set_partition(3, part(100, 10)).unwrap();
+        assert_eq!(mbr.partitions()[3].unwrap().start_lba, 100);
+        assert_eq!(mbr.add_partition(part(200, 10)).unwrap(), 0);
+
+        assert!(matches!(mbr.set_partition_checked(1, part(105, 1)), Err(MBRError::Overlap)));
+        // Replacing a partition does not conflict with itself
+        mbr.set_partition_checked(3, part(105, 1)).unwrap();
+        mbr.delete_partition(3).unwrap();
+        assert!(mbr.partitions()[3].is_none());
+
+        assert!(matches!(mbr.set_partition(4, part(1, 1)), Err(MBRError::InvalidID)));
+        assert!(matches!(mbr.delete_partition(4), Err(MBRError::InvalidID)));
+    }
+
+    #[test]
+    fn custom_geometry() {
+        let default = Geometry::default();
+        assert_eq!((default.heads, default.sectors, default.block_size), (255, 63, 512));
+
+        let start_chs = |geometry: &Geometry| {
+            let mut mbr = MBR::new();
+            mbr.add_partition(part(2048, 1)).unwrap();
+            let mut image = blank_image(1, 512);
+            mbr.write_mbr_with_geometry(&mut image, MbrWriteMode::LbaOnly, geometry).unwrap();
+            [image.get_ref()[447], image.get_ref()[448], image.get_ref()[449]]
+        };
+        assert_eq!(start_chs(&default), [32, 33, 0]);
+        // Cylinder 4, head 0, sector 1
+        assert_eq!(start_chs(&Geometry { heads: 16, sectors: 32, block_size: 512 }), [0, 1, 4]);
+        // Without a usable geometry, the address is maxed out
+        assert_eq!(start_chs(&Geometry { heads: 0, sectors: 0, block_size: 512 }), [0xFE, 0xFF, 0xFF]);
+        assert_eq!(start_chs(&Geometry { heads: 256, sectors: 63, block_size: 512 }), [0xFE, 0xFF, 0xFF]);
+        assert_eq!(start_chs(&Geometry { heads: 255, sectors: 64, block_size: 512 }), [0xFE, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn write_handles_short_writes() {
+        let mut mbr = MBR::new();
+        let part = PartitionEntryBuilder::new()
+            .system_id(0x83)
+            .start_lba(2048)
+            .sectors(4096)
+            .build()
+            .unwrap();
+        mbr.add_partition(part).unwrap();
+
+        let mut expected = blank_image(1, 512);
+        mbr.write_mbr(&mut expected, MbrWriteMode::LbaOnly).unwrap();
+        let mut image = ShortWriter::new(blank_image(1, 512), 2);
+        mbr.write_mbr(&mut image, MbrWriteMode::LbaOnly).unwrap();
+        assert_eq!(image.into_inner().get_ref(), expected.get_ref());
+    }
+}
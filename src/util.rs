@@ -1,6 +1,12 @@
 use std::ops::{Add, Sub};
+use std::fmt;
+#[cfg(any(test, feature = "testutil"))]
+use std::io::{Cursor, Seek, SeekFrom, Write, Result as IOResult};
+#[cfg(any(test, feature = "testutil"))]
+use gpt::{GPTTable, GPTOptions};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Block(pub u64);
 
 impl Block {
@@ -15,7 +21,7 @@ impl Block {
     pub fn from_bytes(bytes: u64, sector_size: u16) -> Option<Block> {
         let sector_size = sector_size as u64;
         if bytes % sector_size == 0 {
-            Some(Block(bytes / 512))
+            Some(Block(bytes / sector_size))
         } else {
             None
         }
@@ -26,13 +32,66 @@ impl Block {
         let sector_size = sector_size as u64;
         (Block(bytes / sector_size), (bytes % sector_size) as u16)
     }
+
+    /// Adds two blocks, returns none on overflow
+    pub fn checked_add(self, other: Block) -> Option<Block> {
+        self.0.checked_add(other.0).map(Block)
+    }
+
+    /// Subtracts two blocks, returns none on underflow
+    pub fn checked_sub(self, other: Block) -> Option<Block> {
+        self.0.checked_sub(other.0).map(Block)
+    }
+
+    /// Subtracts two blocks, stops at Block(0) instead of underflowing
+    pub fn saturating_sub(self, other: Block) -> Block {
+        Block(self.0.saturating_sub(other.0))
+    }
+
+    /// Rounds up to the next multiple of `alignment`. An alignment of 0 leaves the block as is.
+    pub fn align_up(&self, alignment: Block) -> Block {
+        if alignment.0 == 0 {
+            *self
+        } else {
+            Block(self.0.div_ceil(alignment.0) * alignment.0)
+        }
+    }
+
+    /// Rounds down to the previous multiple of `alignment`. An alignment of 0 leaves the block as
+    /// is.
+    pub fn align_down(&self, alignment: Block) -> Block {
+        Block(self.0 - self.0.checked_rem(alignment.0).unwrap_or(0))
+    }
+
+    /// Formats the size of `self.0` blocks in bytes, using the largest fitting binary unit,
+    /// e.g. "512 B" or "1.0 MiB"
+    pub fn human_size(&self, block_size: u16) -> String {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        let bytes = self.to_bytes(block_size);
+        if bytes < 1024 {
+            return format!("{} B", bytes);
+        }
+        let mut size = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 impl Add for Block {
     type Output = Block;
 
     fn add(self, other: Block) -> Block {
-        Block(self.0 + other.0)
+        self.checked_add(other).expect("Block overflow")
     }
 }
 
@@ -40,6 +99,176 @@ impl Sub for Block {
     type Output = Block;
 
     fn sub(self, other: Block) -> Block {
-        Block(self.0 - other.0)
+        self.checked_sub(other).expect("Block underflow")
+    }
+}
+
+/// The geometry of a disk, used to compute CHS addresses
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Geometry {
+    /// Number of heads, at most 255 can be addressed. With more, no CHS addresses are computed.
+    pub heads: u16,
+    /// Number of sectors per track, at most 63 can be addressed. With more, no CHS addresses
+    /// are computed.
+    pub sectors: u16,
+    /// Size of a block in bytes
+    pub block_size: u16
+}
+
+/// 255 heads and 63 sectors per track with 512 byte blocks, as used by most modern tools
+impl Default for Geometry {
+    fn default() -> Geometry {
+        Geometry {
+            heads: 255,
+            sectors: 63,
+            block_size: 512
+        }
+    }
+}
+
+/// Create a zeroed in-memory disk image of `blocks` blocks
+#[cfg(any(test, feature = "testutil"))]
+pub fn blank_image(blocks: u64, block_size: u16) -> Cursor<Vec<u8>> {
+    Cursor::new(vec![0u8; Block(blocks).to_bytes(block_size) as usize])
+}
+
+/// Create an in-memory disk image of `blocks` blocks containing `table`, including its
+/// protective MBR if enabled in `options`
+///
+/// The returned cursor is positioned at the start of the image. Panics if the table can not be
+/// written, e.g. because it does not fit.
+#[cfg(any(test, feature = "testutil"))]
+pub fn gpt_image(table: &GPTTable, blocks: u64, options: &GPTOptions) -> Cursor<Vec<u8>> {
+    let mut image = blank_image(blocks, options.block_size);
+    table.write(&mut image, options).expect("Could not write GPT to image");
+    image.seek(SeekFrom::Start(0)).expect("Seeking in memory can not fail");
+    image
+}
+
+/// A writer which only accepts a limited number of bytes per call, like a pipe or a slow device
+#[cfg(any(test, feature = "testutil"))]
+pub struct ShortWriter<W> {
+    inner: W,
+    max_len: usize
+}
+
+#[cfg(any(test, feature = "testutil"))]
+impl<W> ShortWriter<W> {
+    /// Wrap `inner`, writing at most `max_len` bytes per call
+    pub fn new(inner: W, max_len: usize) -> ShortWriter<W> {
+        ShortWriter { inner, max_len }
+    }
+
+    /// Get back the wrapped writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(any(test, feature = "testutil"))]
+impl<W: Write> Write for ShortWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let len = ::std::cmp::min(buf.len(), self.max_len);
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(any(test, feature = "testutil"))]
+impl<W: Seek> Seek for ShortWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpt::{PartitionEntryBuilder, types};
+
+    #[test]
+    fn from_bytes_uses_sector_size() {
+        assert_eq!(Block::from_bytes(4096, 512), Some(Block(8)));
+        assert_eq!(Block::from_bytes(4096, 1024), Some(Block(4)));
+        assert_eq!(Block::from_bytes(8192, 4096), Some(Block(2)));
+        assert_eq!(Block::from_bytes(4097, 4096), None);
+        assert_eq!(Block::from_bytes_offset(4097, 4096), (Block(1), 1));
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        assert_eq!(Block(1).saturating_sub(Block(5)), Block(0));
+        assert_eq!(Block(1).checked_sub(Block(5)), None);
+        assert_eq!(Block(5).checked_sub(Block(1)), Some(Block(4)));
+        assert_eq!(Block(u64::MAX).checked_add(Block(1)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Block underflow")]
+    fn subtraction_underflow() {
+        let _ = Block(1) - Block(2);
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(Block(2048).align_up(Block(2048)), Block(2048));
+        assert_eq!(Block(2048).align_down(Block(2048)), Block(2048));
+        assert_eq!(Block(34).align_up(Block(2048)), Block(2048));
+        assert_eq!(Block(3000).align_down(Block(2048)), Block(2048));
+        // An alignment of 0 leaves the block alone
+        assert_eq!(Block(33).align_up(Block(0)), Block(33));
+        assert_eq!(Block(33).align_down(Block(0)), Block(33));
+    }
+
+    #[test]
+    fn human_size() {
+        assert_eq!(Block(2048).human_size(512), "1.0 MiB");
+        assert_eq!(Block(1).human_size(512), "512 B");
+        assert_eq!(Block(3).human_size(512), "1.5 KiB");
+        assert_eq!(Block(42).to_string(), "42");
+    }
+
+    #[test]
+    fn hash() {
+        use std::collections::HashSet;
+
+        let blocks = [Block(1), Block(2), Block(1)].iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(blocks.len(), 2);
+        let part = PartitionEntryBuilder::new()
+            .part_type(*types::LINUX_SWAP)
+            .range(Block(40), Block(50))
+            .build()
+            .unwrap();
+        let mut renamed = part.clone();
+        renamed.set_name("swap").unwrap();
+        let parts = vec![part.clone(), part, renamed].into_iter().collect::<HashSet<_>>();
+        assert_eq!(parts.len(), 2);
+    }
+
+    #[test]
+    fn blank_image_size() {
+        let image = blank_image(10, 4096);
+        assert_eq!(image.get_ref().len(), 40960);
+        assert!(image.get_ref().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn gpt_image_round_trip() {
+        let options = GPTOptions::default();
+        let mut table = GPTTable::create(300, &options).unwrap();
+        let part = PartitionEntryBuilder::new()
+            .part_type(*types::LINUX_SWAP)
+            .range(Block(40), Block(50))
+            .build()
+            .unwrap();
+        table.set_partition(0, part).unwrap();
+
+        let mut image = gpt_image(&table, 300, &options);
+        assert_eq!(image.position(), 0);
+        assert!(GPTTable::load(&mut image, &options).unwrap() == table);
     }
 }
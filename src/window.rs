@@ -1,6 +1,11 @@
 use std::io;
+use std::cmp;
 
-struct Window <'a, T: 'a + io::Seek> {
+/// A view on a region of a larger stream
+///
+/// Offsets are relative to the start of the region and all IO is limited to it. This allows
+/// passing for example a disk image embedded in a larger file to `MBR::load` or `GPT::load`.
+pub struct Window <'a, T: 'a + io::Seek> {
     backend: &'a mut T,
     start: u64,
     len: u64,
@@ -8,6 +13,7 @@ struct Window <'a, T: 'a + io::Seek> {
 }
 
 impl <'a, T: io::Seek> Window <'a, T> {
+    /// Create a window on `len` bytes of `backend`, starting at byte offset `start`
     pub fn new(backend: &'a mut T, start: u64, len: u64 ) -> Window<'a, T> {
         Window {
             backend,
@@ -17,15 +23,40 @@ impl <'a, T: io::Seek> Window <'a, T> {
         }
     }
 
+    /// Gets the offset of the window within the backend
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Gets the length of the window in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the window has a length of 0
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     fn clamp_seek(&self, offset: i64) -> u64 {
         if offset < 0 {
             0
         } else if offset >= self.len as i64 {
-            self.len - 1
+            self.len
         } else {
             offset as u64
         }
     }
+
+    /// Number of bytes between the current position and the end of the window
+    fn remaining(&self) -> u64 {
+        self.len - self.current_offset
+    }
+
+    /// Limits a buffer length to the bytes left in the window
+    fn clamp_len(&self, len: usize) -> usize {
+        cmp::min(len as u64, self.remaining()) as usize
+    }
 }
 
 impl <'a, T: io::Seek> io::Seek for Window<'a, T> {
@@ -35,7 +66,7 @@ impl <'a, T: io::Seek> io::Seek for Window<'a, T> {
                 self.current_offset = self.clamp_seek(offset as i64);
             },
             io::SeekFrom::End(offset) => {
-                self.current_offset = self.clamp_seek((self.start + self.len) as i64 + offset);
+                self.current_offset = self.clamp_seek(self.len as i64 + offset);
             },
             io::SeekFrom::Current(offset) => {
                 self.current_offset = self.clamp_seek(self.current_offset as i64 + offset);
@@ -49,7 +80,9 @@ impl <'a, T: io::Seek> io::Seek for Window<'a, T> {
 
 impl <'a, T: io::Seek + io::Write> io::Write for Window<'a, T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let bytes = self.backend.write(buf)?;
+        let len = self.clamp_len(buf.len());
+        self.backend.seek(io::SeekFrom::Start(self.start + self.current_offset))?;
+        let bytes = self.backend.write(&buf[..len])?;
         self.current_offset += bytes as u64;
         Ok(bytes)
     }
@@ -59,10 +92,133 @@ impl <'a, T: io::Seek + io::Write> io::Write for Window<'a, T> {
     }
 }
 
+/// Reads only return less than requested at the end of the window, or if the backend ends
+/// before it. Ok(0) is returned exactly at the end of the window.
 impl <'a, T: io::Seek + io::Read> io::Read for Window<'a, T> {
     fn read(&mut self, buf: &mut[u8]) -> io::Result<usize> {
-        let bytes = self.backend.read(buf)?;
+        let len = self.clamp_len(buf.len());
+        self.backend.seek(io::SeekFrom::Start(self.start + self.current_offset))?;
+        let mut bytes = 0;
+        // The backend may return short reads, keep going until the buffer is full
+        while bytes < len {
+            match self.backend.read(&mut buf[bytes..len]) {
+                Ok(0) => break,
+                Ok(n) => bytes += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
         self.current_offset += bytes as u64;
         Ok(bytes)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use gpt::{GPTTable, GPTOptions};
+    use mbr::{MBR, PartitionEntryBuilder, MbrWriteMode};
+    use util::blank_image;
+
+    #[test]
+    fn bounded_io() {
+        let mut image = Cursor::new(vec![0u8; 64]);
+        {
+            let mut window = Window::new(&mut image, 16, 16);
+            assert_eq!(window.seek(SeekFrom::End(-4)).unwrap(), 12);
+            assert_eq!(window.write(&[0xFF; 8]).unwrap(), 4);
+            assert_eq!(window.write(&[0xFF; 8]).unwrap(), 0);
+            assert_eq!(window.seek(SeekFrom::Current(100)).unwrap(), 16);
+
+            window.seek(SeekFrom::Start(10)).unwrap();
+            let mut buf = [0u8; 16];
+            assert_eq!(window.read(&mut buf).unwrap(), 6);
+            assert_eq!(&buf[..6], &[0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+            assert!(window.read_exact(&mut buf).is_err());
+        }
+        // Nothing outside the window was touched
+        assert!(image.get_ref()[..28].iter().all(|&b| b == 0));
+        assert!(image.get_ref()[32..].iter().all(|&b| b == 0));
+    }
+
+    fn pattern_image() -> Cursor<Vec<u8>> {
+        Cursor::new((0..1 << 20).map(|i| (i % 251) as u8).collect())
+    }
+
+    #[test]
+    fn offsets_into_backend() {
+        let mut image = pattern_image();
+        {
+            let mut window = Window::new(&mut image, 4096, 1024);
+            let mut buf = [0u8; 4];
+            window.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[0], (4096 % 251) as u8);
+
+            // Reads and writes stop at the end of the window
+            window.seek(SeekFrom::End(-2)).unwrap();
+            let mut buf = [0u8; 10];
+            assert_eq!(window.read(&mut buf).unwrap(), 2);
+            assert_eq!(buf[1], ((4096 + 1023) % 251) as u8);
+            assert_eq!(window.read(&mut buf).unwrap(), 0);
+            window.seek(SeekFrom::Start(1020)).unwrap();
+            assert_eq!(window.write(&[0xAA; 10]).unwrap(), 4);
+        }
+        assert_eq!(image.get_ref()[4096 + 1023], 0xAA);
+        assert_eq!(image.get_ref()[4096 + 1024], ((4096 + 1024) % 251) as u8);
+    }
+
+    #[test]
+    fn embedded_gpt() {
+        let options = GPTOptions::default();
+        let table = GPTTable::create(1000, &options).unwrap();
+        let mut image = blank_image(2000, 512);
+        table.write(&mut Window::new(&mut image, 500 * 512, 1000 * 512), &options).unwrap();
+        assert!(!GPTTable::exists(&mut image, &options).unwrap());
+
+        let loaded = GPTTable::load(&mut Window::new(&mut image, 500 * 512, 1000 * 512), &options).unwrap();
+        assert!(loaded == table);
+    }
+
+    #[test]
+    fn embedded_mbr() {
+        let mut mbr = MBR::new();
+        let part = PartitionEntryBuilder::new().system_id(0x83).start_lba(1).sectors(10).build().unwrap();
+        mbr.add_partition(part).unwrap();
+        let mut image = blank_image(100, 512);
+        mbr.write_mbr(&mut Window::new(&mut image, 50 * 512, 50 * 512), MbrWriteMode::LbaOnly).unwrap();
+
+        assert!(MBR::load(&mut image, false).is_err());
+        let loaded = MBR::load(&mut Window::new(&mut image, 50 * 512, 50 * 512), false).unwrap();
+        assert_eq!(loaded.partitions()[0].unwrap().start_lba, 1);
+    }
+
+    /// Hands out a single byte per read
+    struct ByteReader(Cursor<Vec<u8>>);
+
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    impl Seek for ByteReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn fills_buffers_until_end() {
+        let mut backend = ByteReader(pattern_image());
+        let mut window = Window::new(&mut backend, 10, 20);
+        let mut buf = [0u8; 15];
+        assert_eq!(window.read(&mut buf).unwrap(), 15);
+        assert_eq!(buf[14], 24);
+        // The rest of the window, then EOF
+        assert_eq!(window.read(&mut buf).unwrap(), 5);
+        assert_eq!(buf[4], 29);
+        assert_eq!(window.read(&mut buf).unwrap(), 0);
+    }
+}